@@ -1,40 +1,348 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
 use anyhow::anyhow;
-use derive_more::Constructor;
+use derive_more::Display;
+use itertools::Itertools;
 
-use crate::scanner::{Literal, Token};
+use crate::{
+    errors::{ErrorCode, LoxError},
+    natives::{native_error, NativeFunction},
+    scanner::{Literal, Span, Token},
+};
 
-#[allow(dead_code)]
+#[derive(Debug, Display)]
 pub enum UnOp {
+    #[display("-")]
     Minus,
+    #[display("!")]
     Bang,
+    #[display("~")]
+    Tilde,
 }
 
-#[allow(dead_code)]
+#[derive(Debug, Display)]
 pub enum BinOp {
-    Bang,
+    #[display("!=")]
     BangEqual,
-    Equal,
+    #[display("==")]
     EqualEqual,
+    #[display(">")]
     Greater,
+    #[display(">=")]
     GreaterEqual,
+    #[display("<")]
     Less,
+    #[display("<=")]
     LessEqual,
+    #[display("+")]
     Plus,
+    #[display("-")]
     Minus,
+    #[display("*")]
     Star,
+    #[display("/")]
     Slash,
+    #[display("%")]
+    Percent,
+    #[display("**")]
+    StarStar,
+    #[display("&")]
+    Ampersand,
+    #[display("|")]
+    Pipe,
+    #[display("^")]
+    Caret,
+    #[display("<<")]
+    LessLess,
+    #[display(">>")]
+    GreaterGreater,
+    #[display("..")]
+    DotDot,
+    #[display("..=")]
+    DotDotEqual,
+    /// The left operand unless it is nil; the right one is only evaluated
+    /// if it is.
+    #[display("??")]
+    QuestionQuestion,
+}
+
+impl BinOp {
+    /// Operators that work on the bits of 32-bit integers.
+    pub fn is_bitwise(&self) -> bool {
+        matches!(
+            self,
+            Self::Ampersand | Self::Pipe | Self::Caret | Self::LessLess | Self::GreaterGreater
+        )
+    }
 }
 
-#[allow(dead_code)]
-#[derive(Default, Clone)]
+/// Values compare equal only if they have the same type and the same value;
+/// there are no implicit conversions, so `1 == "1"` is false.
+#[derive(Debug, Default, Clone, PartialEq, Display)]
 pub enum LitKind {
     Number(f32),
     String(String),
     Boolean(bool),
+    List(LoxList),
+    Map(LoxMap),
+    Range(LoxRange),
+    Native(NativeFunction),
     #[default]
+    #[display("nil")]
     Nil,
 }
 
+/// A value a script evaluated to, as natives and host code see it.
+pub type Value = LitKind;
+
+/// The value of a list literal. Lists compare element by element, so
+/// `[1, "a"] == [1, "a"]`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LoxList {
+    items: Vec<LitKind>,
+}
+
+impl LoxList {
+    pub fn new(items: Vec<LitKind>) -> Self {
+        Self { items }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `None` unless `index` is a whole number within the list.
+    pub fn get(&self, index: f32) -> Option<&LitKind> {
+        self.items.get(to_index(index, self.len())?)
+    }
+
+    /// The elements from `start` up to, but not including, `end`.
+    pub fn slice(&self, start: usize, end: usize) -> Option<Self> {
+        Some(Self::new(self.items.get(start..end)?.to_vec()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &LitKind> {
+        self.items.iter()
+    }
+}
+
+/// The numbers from `start` up to `end` in steps of one, e.g. `0..3` is 0, 1
+/// and 2, and `0..=3` includes the 3. They are never materialized, so
+/// `0..1e9` costs as much as `0..1`. Ranges are equal if they hold the same
+/// numbers, so `0..3 == 0..=2`.
+#[derive(Debug, Clone, Copy, Display)]
+#[display("{start}{}{end}", if *inclusive { "..=" } else { ".." })]
+pub struct LoxRange {
+    start: f32,
+    end: f32,
+    inclusive: bool,
+}
+
+impl LoxRange {
+    pub fn new(start: f32, end: f32, inclusive: bool) -> Self {
+        Self {
+            start,
+            end,
+            inclusive,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let span = self.end - self.start;
+        let len = match self.inclusive {
+            true => span.floor() + 1.0,
+            false => span.ceil(),
+        };
+        // NaN and negative lengths saturate to 0
+        len.max(0.0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `None` unless `index` is a whole number below `len`.
+    pub fn get(&self, index: f32) -> Option<f32> {
+        to_index(index, self.len()).map(|i| self.start + i as f32)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = f32> {
+        let start = self.start;
+        (0..self.len()).map(move |i| start + i as f32)
+    }
+}
+
+impl PartialEq for LoxRange {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && (self.is_empty() || self.start == other.start)
+    }
+}
+
+/// `n` as an index below `bound`: `None` unless it's a whole number in
+/// `0..bound`.
+pub fn to_index(n: f32, bound: usize) -> Option<usize> {
+    (n.fract() == 0.0 && n >= 0.0 && n < bound as f32).then_some(n as usize)
+}
+
+/// Strings inside a collection are quoted, so `["1", 1]` doesn't print as `[1, 1]`.
+fn fmt_item(f: &mut fmt::Formatter<'_>, item: &LitKind) -> fmt::Result {
+    match item {
+        LitKind::String(s) => write!(f, "\"{s}\""),
+        _ => write!(f, "{item}"),
+    }
+}
+
+impl fmt::Display for LoxList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            fmt_item(f, item)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// What a map is keyed on: floats aren't hashable, so numbers go by their
+/// bits, with -0 and 0 made the same key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MapKey {
+    String(String),
+    Number(u32),
+}
+
+impl MapKey {
+    fn new(key: &LitKind) -> Option<Self> {
+        match key {
+            LitKind::String(s) => Some(Self::String(s.clone())),
+            LitKind::Number(n) if !n.is_nan() => Some(Self::Number((n + 0.0).to_bits())),
+            _ => None,
+        }
+    }
+}
+
+/// The value of a map literal, keyed on strings and numbers. Entries stay
+/// in the order their key was first inserted, which is the order they
+/// print in. Two maps are equal if they have the same entries, in any order.
+#[derive(Debug, Default, Clone)]
+pub struct LoxMap {
+    entries: Vec<(LitKind, LitKind)>,
+    index: HashMap<MapKey, usize>,
+}
+
+impl LoxMap {
+    /// Replaces the value of an existing key in place. `false` if `key`
+    /// can't be a key, i.e. isn't a string or a number other than NaN.
+    pub fn insert(&mut self, key: LitKind, value: LitKind) -> bool {
+        let Some(k) = MapKey::new(&key) else {
+            return false;
+        };
+        match self.index.get(&k) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(k, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        true
+    }
+
+    pub fn get(&self, key: &LitKind) -> Option<&LitKind> {
+        let i = self.index.get(&MapKey::new(key)?)?;
+        Some(&self.entries[*i].1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&LitKind, &LitKind)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+}
+
+impl PartialEq for LoxMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl fmt::Display for LoxMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            fmt_item(f, key)?;
+            write!(f, ": ")?;
+            fmt_item(f, value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl LitKind {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Number(_) => "number",
+            Self::String(_) => "string",
+            Self::Boolean(_) => "boolean",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
+            Self::Range(_) => "range",
+            Self::Native(_) => "function",
+            Self::Nil => "nil",
+        }
+    }
+
+    /// `nil` and `false` are falsey, everything else (even `0` and `""`) is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Nil | Self::Boolean(false))
+    }
+}
+
+/// Formats `n` for people: exactly `decimals` digits after a `.`, and the
+/// integer part grouped in threes by `thousands_sep`. Like the rest of the
+/// number handling this never looks at the process locale.
+pub fn format_number(n: f32, decimals: usize, thousands_sep: &str) -> String {
+    if !n.is_finite() {
+        return n.to_string();
+    }
+    let fixed = format!("{:.*}", decimals, n.abs());
+    let (int, frac) = fixed.split_once('.').unwrap_or((&fixed, ""));
+    let mut out = String::new();
+    // Decided after rounding, so -0.001 to two places is 0.00, not -0.00
+    if n < 0.0 && fixed.bytes().any(|b| matches!(b, b'1'..=b'9')) {
+        out.push('-');
+    }
+    for (i, c) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i) % 3 == 0 {
+            out += thousands_sep;
+        }
+        out.push(c);
+    }
+    if !frac.is_empty() {
+        out.push('.');
+        out += frac;
+    }
+    out
+}
+
 // impl Default for LitKind {
 //     fn default() -> Self {
 //         match  {
@@ -48,31 +356,63 @@ pub enum LitKind {
 //
 //
 
+/// Applies a binary operator to two operands of the same type, `None` if
+/// the operator doesn't work on that type.
 pub trait BinaryEval<T> {
-    fn bin_eval(&self, a: T, b: T) -> Option<T>;
+    fn bin_eval(&self, a: T, b: T) -> Option<LitKind>;
 }
 
 impl BinaryEval<f32> for BinOp {
-    fn bin_eval(&self, a: f32, b: f32) -> Option<f32> {
+    fn bin_eval(&self, a: f32, b: f32) -> Option<LitKind> {
         Some(match self {
-            Self::Plus => a + b,
-            Self::Minus => a - b,
-            Self::Star => a * b,
-            Self::Slash => a / b,
+            Self::Plus => LitKind::Number(a + b),
+            Self::Minus => LitKind::Number(a - b),
+            Self::Star => LitKind::Number(a * b),
+            Self::Slash => LitKind::Number(a / b),
+            // The remainder takes the sign of the dividend, so -7 % 3 is -1
+            Self::Percent => LitKind::Number(a % b),
+            Self::StarStar => LitKind::Number(a.powf(b)),
+            Self::DotDot => LitKind::Range(LoxRange::new(a, b, false)),
+            Self::DotDotEqual => LitKind::Range(LoxRange::new(a, b, true)),
+            Self::Greater => LitKind::Boolean(a > b),
+            Self::GreaterEqual => LitKind::Boolean(a >= b),
+            Self::Less => LitKind::Boolean(a < b),
+            Self::LessEqual => LitKind::Boolean(a <= b),
             _ => return None,
         })
     }
 }
 
+/// Strings order lexicographically by code point, so `"B" < "a"`.
 impl BinaryEval<String> for BinOp {
-    fn bin_eval(&self, a: String, b: String) -> Option<String> {
+    fn bin_eval(&self, a: String, b: String) -> Option<LitKind> {
         Some(match self {
-            Self::Plus => a + b.as_str(),
+            Self::Plus => LitKind::String(a + b.as_str()),
+            Self::Greater => LitKind::Boolean(a > b),
+            Self::GreaterEqual => LitKind::Boolean(a >= b),
+            Self::Less => LitKind::Boolean(a < b),
+            Self::LessEqual => LitKind::Boolean(a <= b),
             _ => return None,
         })
     }
 }
 
+/// Bitwise operators, on numbers the interpreter has already checked are
+/// 32-bit integers. Shifts fill with the sign bit, so `-8 >> 1` is -4.
+impl BinaryEval<i32> for BinOp {
+    fn bin_eval(&self, a: i32, b: i32) -> Option<LitKind> {
+        let n = match self {
+            Self::Ampersand => a & b,
+            Self::Pipe => a | b,
+            Self::Caret => a ^ b,
+            Self::LessLess => a.checked_shl(b.try_into().ok()?)?,
+            Self::GreaterGreater => a.checked_shr(b.try_into().ok()?)?,
+            _ => return None,
+        };
+        Some(LitKind::Number(n as f32))
+    }
+}
+
 pub trait UnaryEval<T> {
     fn unary_eval(&self, a: T) -> Option<T>;
 }
@@ -81,36 +421,234 @@ impl UnaryEval<f32> for UnOp {
     fn unary_eval(&self, a: f32) -> Option<f32> {
         match self {
             Self::Minus => Some(-a),
-            Self::Bang => None,
+            Self::Bang | Self::Tilde => None,
         }
     }
 }
 
-impl UnaryEval<bool> for UnOp {
-    fn unary_eval(&self, a: bool) -> Option<bool> {
+impl UnaryEval<i32> for UnOp {
+    fn unary_eval(&self, a: i32) -> Option<i32> {
         match self {
-            Self::Minus => None,
-            Self::Bang => Some(!a),
+            Self::Tilde => Some(!a),
+            Self::Minus | Self::Bang => None,
         }
     }
 }
 
-#[allow(dead_code)]
+/// What a `match` arm compares its value against.
+#[derive(Debug)]
+pub enum Pattern {
+    /// Equal to this value.
+    Literal(LitKind),
+    /// Anything, bound to the name in the arm's body. `_` binds nothing.
+    Binding(String),
+    /// A list with exactly as many items, each matching its pattern.
+    List(Vec<Pattern>),
+    /// A map with at least these keys, each value matching its pattern.
+    Map(Vec<(LitKind, Pattern)>),
+}
+
+impl Pattern {
+    /// Whether `value` matches, adding what the pattern binds to `bindings`.
+    pub fn bind(&self, value: &LitKind, bindings: &mut HashMap<String, LitKind>) -> bool {
+        match (self, value) {
+            (Self::Literal(lit), value) => lit == value,
+            (Self::Binding(name), _) if name == "_" => true,
+            (Self::Binding(name), value) => {
+                bindings.insert(name.clone(), value.clone());
+                true
+            }
+            (Self::List(patterns), LitKind::List(list)) => {
+                patterns.len() == list.len()
+                    && patterns
+                        .iter()
+                        .zip(list.iter())
+                        .all(|(p, v)| p.bind(v, bindings))
+            }
+            (Self::Map(entries), LitKind::Map(map)) => entries
+                .iter()
+                .all(|(key, p)| map.get(key).is_some_and(|v| p.bind(v, bindings))),
+            _ => false,
+        }
+    }
+}
+
+/// Written the way it is in the source.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let literal = |lit: &LitKind| match lit {
+            LitKind::String(s) => format!("\"{s}\""),
+            _ => lit.to_string(),
+        };
+        match self {
+            Self::Literal(lit) => write!(f, "{}", literal(lit)),
+            Self::Binding(name) => write!(f, "{name}"),
+            Self::List(items) => write!(f, "[{}]", items.iter().join(", ")),
+            Self::Map(entries) => write!(
+                f,
+                "{{{}}}",
+                entries
+                    .iter()
+                    .map(|(k, p)| format!("{}: {p}", literal(k)))
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub enum ExprKind {
     Literal(LitKind),
     Unary(Box<Expr>, UnOp),
     Binary(Box<Expr>, Box<Expr>, BinOp),
     Grouping(Box<Expr>),
+    List(Vec<Expr>),
+    /// Keys and values, in source order.
+    Map(Vec<(Expr, Expr)>),
+    /// The list or map, then the index or key.
+    Index(Box<Expr>, Box<Expr>),
+    /// A list or string, then where the slice starts and ends, if given.
+    Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>),
+    Variable(String),
+    /// The callee, then the arguments.
+    Call(Box<Expr>, Vec<Expr>),
+    /// `...list`, passing the items as separate arguments. Only ever an
+    /// argument of a call.
+    Spread(Box<Expr>),
+    /// The matched value, the arms in order, then the `else` arm.
+    Match(Box<Expr>, Vec<(Pattern, Expr)>, Box<Expr>),
+}
+
+/// Identifies one node of the tree for as long as the process runs, so
+/// side tables (scope depths, warnings, coverage) can be keyed on it.
+/// Ids are handed out in creation order, so children precede their parent.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    fn fresh() -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
 /* NOTE: This will get more fields for diagnostics
 * Note that the key here is that an expr is just one type of node in AST,
 * which is why this representation works.
+* `token` is the one that best identifies the node (e.g. the operator),
+* while `span` covers all of the node's source, children included.
 */
-#[derive(Constructor)]
+#[derive(Debug)]
 pub struct Expr {
+    pub id: NodeId,
     pub kind: ExprKind,
     pub token: Token,
+    pub span: Span,
+    /// Height of the tree under this node, a literal is 1.
+    pub depth: usize,
+}
+
+impl ExprKind {
+    fn depth(&self) -> usize {
+        1 + match self {
+            ExprKind::Literal(_) | ExprKind::Variable(_) => 0,
+            ExprKind::Unary(inner, _) | ExprKind::Grouping(inner) | ExprKind::Spread(inner) => {
+                inner.depth
+            }
+            ExprKind::Binary(left, right, _) | ExprKind::Index(left, right) => {
+                left.depth.max(right.depth)
+            }
+            ExprKind::List(items) => items.iter().map(|e| e.depth).max().unwrap_or(0),
+            ExprKind::Map(entries) => entries
+                .iter()
+                .map(|(k, v)| k.depth.max(v.depth))
+                .max()
+                .unwrap_or(0),
+            ExprKind::Slice(target, start, end) => [start, end]
+                .into_iter()
+                .flatten()
+                .fold(target.depth, |d, e| d.max(e.depth)),
+            ExprKind::Call(callee, args) => args.iter().fold(callee.depth, |d, e| d.max(e.depth)),
+            ExprKind::Match(subject, arms, otherwise) => arms
+                .iter()
+                .fold(subject.depth.max(otherwise.depth), |d, (_, e)| {
+                    d.max(e.depth)
+                }),
+        }
+    }
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, token: Token) -> Self {
+        let span = match &kind {
+            ExprKind::Literal(_) | ExprKind::Variable(_) => token.span,
+            ExprKind::Unary(inner, _) | ExprKind::Grouping(inner) | ExprKind::Spread(inner) => {
+                token.span.cover(inner.span)
+            }
+            ExprKind::Binary(left, right, _) | ExprKind::Index(left, right) => {
+                token.span.cover(left.span).cover(right.span)
+            }
+            ExprKind::List(items) => items.iter().fold(token.span, |s, e| s.cover(e.span)),
+            ExprKind::Map(entries) => entries
+                .iter()
+                .fold(token.span, |s, (k, v)| s.cover(k.span).cover(v.span)),
+            ExprKind::Slice(target, start, end) => [start, end]
+                .into_iter()
+                .flatten()
+                .fold(token.span.cover(target.span), |s, e| s.cover(e.span)),
+            ExprKind::Call(callee, args) => args
+                .iter()
+                .fold(token.span.cover(callee.span), |s, e| s.cover(e.span)),
+            ExprKind::Match(subject, arms, otherwise) => arms.iter().fold(
+                token.span.cover(subject.span).cover(otherwise.span),
+                |s, (_, e)| s.cover(e.span),
+            ),
+        };
+        Self {
+            id: NodeId::fresh(),
+            depth: kind.depth(),
+            kind,
+            token,
+            span,
+        }
+    }
+
+    /// The node's direct children, in source order.
+    pub fn children(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Literal(_) | ExprKind::Variable(_) => vec![],
+            ExprKind::Unary(inner, _) | ExprKind::Grouping(inner) | ExprKind::Spread(inner) => {
+                vec![inner]
+            }
+            ExprKind::Binary(left, right, _) | ExprKind::Index(left, right) => vec![left, right],
+            ExprKind::List(items) => items.iter().collect(),
+            ExprKind::Map(entries) => entries.iter().flat_map(|(k, v)| [k, v]).collect(),
+            ExprKind::Slice(target, start, end) => [Some(target), start.as_ref(), end.as_ref()]
+                .into_iter()
+                .flatten()
+                .map(|e| &**e)
+                .collect(),
+            ExprKind::Call(callee, args) => std::iter::once(&**callee).chain(args).collect(),
+            ExprKind::Match(subject, arms, otherwise) => std::iter::once(&**subject)
+                .chain(arms.iter().map(|(_, body)| body))
+                .chain([&**otherwise])
+                .collect(),
+        }
+    }
+
+    /// Moves the kind out, leaving `nil` in its place. `Expr` has a `Drop`,
+    /// so this is how a folder gets at the children.
+    pub fn take_kind(&mut self) -> ExprKind {
+        std::mem::replace(&mut self.kind, ExprKind::Literal(LitKind::Nil))
+    }
+}
+
+// NOTE: Dropping a tree recurses just like walking it does
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let kind = self.take_kind();
+        with_stack(|| drop(kind));
+    }
 }
 
 impl TryFrom<Literal> for LitKind {
@@ -125,91 +663,576 @@ impl TryFrom<Literal> for LitKind {
     }
 }
 
+/*
+* NOTE: Conversions between values and Rust types, for embedders and
+* natives. Converting out fails with the same error a native raises for a
+* wrong argument, so `f64::try_from(args[0].clone())?` reads naturally in
+* a native's body and is reported at the call.
+*/
+
+impl From<f32> for LitKind {
+    fn from(n: f32) -> Self {
+        Self::Number(n)
+    }
+}
+
+/// Numbers are 32-bit for now, so this rounds.
+impl From<f64> for LitKind {
+    fn from(n: f64) -> Self {
+        Self::Number(n as f32)
+    }
+}
+
+impl From<bool> for LitKind {
+    fn from(b: bool) -> Self {
+        Self::Boolean(b)
+    }
+}
+
+impl From<&str> for LitKind {
+    fn from(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+}
+
+impl From<String> for LitKind {
+    fn from(s: String) -> Self {
+        Self::String(s)
+    }
+}
+
+/// `None` is nil.
+impl<T: Into<LitKind>> From<Option<T>> for LitKind {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Nil, Into::into)
+    }
+}
+
+impl<T: Into<LitKind>> From<Vec<T>> for LitKind {
+    fn from(items: Vec<T>) -> Self {
+        Self::List(LoxList::new(items.into_iter().map(Into::into).collect()))
+    }
+}
+
+/// The entries are in no particular order, as in the `HashMap`.
+impl<T: Into<LitKind>> From<HashMap<String, T>> for LitKind {
+    fn from(entries: HashMap<String, T>) -> Self {
+        let mut map = LoxMap::default();
+        for (key, value) in entries {
+            map.insert(Self::String(key), value.into());
+        }
+        Self::Map(map)
+    }
+}
+
+fn wrong_type(expected: &str, found: &LitKind) -> LoxError {
+    native_error(
+        ErrorCode::InvalidOperand,
+        &format!("expected a {expected}, got a {}", found.type_name()),
+    )
+}
+
+impl TryFrom<LitKind> for f32 {
+    type Error = LoxError;
+
+    fn try_from(value: LitKind) -> Result<Self, Self::Error> {
+        match value {
+            LitKind::Number(n) => Ok(n),
+            other => Err(wrong_type("number", &other)),
+        }
+    }
+}
+
+impl TryFrom<LitKind> for f64 {
+    type Error = LoxError;
+
+    fn try_from(value: LitKind) -> Result<Self, Self::Error> {
+        f32::try_from(value).map(f64::from)
+    }
+}
+
+impl TryFrom<LitKind> for bool {
+    type Error = LoxError;
+
+    fn try_from(value: LitKind) -> Result<Self, Self::Error> {
+        match value {
+            LitKind::Boolean(b) => Ok(b),
+            other => Err(wrong_type("boolean", &other)),
+        }
+    }
+}
+
+impl TryFrom<LitKind> for String {
+    type Error = LoxError;
+
+    fn try_from(value: LitKind) -> Result<Self, Self::Error> {
+        match value {
+            LitKind::String(s) => Ok(s),
+            other => Err(wrong_type("string", &other)),
+        }
+    }
+}
+
+impl<T: TryFrom<LitKind, Error = LoxError>> TryFrom<LitKind> for Vec<T> {
+    type Error = LoxError;
+
+    fn try_from(value: LitKind) -> Result<Self, Self::Error> {
+        match value {
+            LitKind::List(list) => list.iter().cloned().map(T::try_from).collect(),
+            other => Err(wrong_type("list", &other)),
+        }
+    }
+}
+
+/// Fails for maps with number keys, which have no `String` to go under.
+impl<T: TryFrom<LitKind, Error = LoxError>> TryFrom<LitKind> for HashMap<String, T> {
+    type Error = LoxError;
+
+    fn try_from(value: LitKind) -> Result<Self, Self::Error> {
+        let LitKind::Map(map) = value else {
+            return Err(wrong_type("map", &value));
+        };
+        map.iter()
+            .map(|(k, v)| Ok((String::try_from(k.clone())?, T::try_from(v.clone())?)))
+            .collect()
+    }
+}
+
+/*
+* NOTE: Every pass over the tree is a Visitor. `walk_expr` dispatches on the
+* node kind to the matching `visit_*` method, and each pass decides itself
+* whether and how to recurse, returning whatever `Output` it computes. A
+* `visit_*` method a pass doesn't override visits the node's children and
+* hands what they came to to `combine`, so a pass only spells out the
+* nodes it cares about. Groupings are looked through unless overridden.
+*/
 pub trait Visitor: Sized {
-    fn visit_expr(&mut self, expr: &Expr) -> () {
+    type Output;
+
+    fn visit_expr(&mut self, expr: &Expr) -> Self::Output {
         walk_expr(self, expr)
     }
+
+    /// What a node comes to, given what its children came to, in source
+    /// order. Only called for nodes whose `visit_*` isn't overridden.
+    fn combine(&mut self, expr: &Expr, children: Vec<Self::Output>) -> Self::Output;
+
+    fn visit_literal(&mut self, expr: &Expr, _lit: &LitKind) -> Self::Output {
+        walk_children(self, expr)
+    }
+
+    fn visit_unary(&mut self, expr: &Expr, _inner: &Expr, _op: &UnOp) -> Self::Output {
+        walk_children(self, expr)
+    }
+
+    fn visit_binary(
+        &mut self,
+        expr: &Expr,
+        _left: &Expr,
+        _right: &Expr,
+        _op: &BinOp,
+    ) -> Self::Output {
+        walk_children(self, expr)
+    }
+
+    fn visit_grouping(&mut self, _expr: &Expr, inner: &Expr) -> Self::Output {
+        self.visit_expr(inner)
+    }
+
+    fn visit_list(&mut self, expr: &Expr, _items: &[Expr]) -> Self::Output {
+        walk_children(self, expr)
+    }
+
+    fn visit_map(&mut self, expr: &Expr, _entries: &[(Expr, Expr)]) -> Self::Output {
+        walk_children(self, expr)
+    }
+
+    fn visit_index(&mut self, expr: &Expr, _list: &Expr, _index: &Expr) -> Self::Output {
+        walk_children(self, expr)
+    }
+
+    fn visit_slice(
+        &mut self,
+        expr: &Expr,
+        _target: &Expr,
+        _start: Option<&Expr>,
+        _end: Option<&Expr>,
+    ) -> Self::Output {
+        walk_children(self, expr)
+    }
+
+    fn visit_variable(&mut self, expr: &Expr, _name: &str) -> Self::Output {
+        walk_children(self, expr)
+    }
+
+    fn visit_call(&mut self, expr: &Expr, _callee: &Expr, _args: &[Expr]) -> Self::Output {
+        walk_children(self, expr)
+    }
+
+    fn visit_spread(&mut self, expr: &Expr, _inner: &Expr) -> Self::Output {
+        walk_children(self, expr)
+    }
+
+    fn visit_match(
+        &mut self,
+        expr: &Expr,
+        _subject: &Expr,
+        _arms: &[(Pattern, Expr)],
+        _otherwise: &Expr,
+    ) -> Self::Output {
+        walk_children(self, expr)
+    }
 }
 
-pub fn walk_expr<V>(v: &mut V, expr: &Expr) -> ()
+pub fn walk_expr<V>(v: &mut V, expr: &Expr) -> V::Output
 where
     V: Visitor,
 {
-    match &expr.kind {
-        ExprKind::Binary(left, right, _) => {
-            v.visit_expr(left);
-            v.visit_expr(right);
-        }
-        ExprKind::Unary(expr, _) => {
-            v.visit_expr(expr);
-        }
-        ExprKind::Grouping(expr) => {
-            v.visit_expr(expr);
-        }
-        _ => {}
-    }
-}
-
-// pub struct PrettyPrinter {}
-
-// impl Visitor for PrettyPrinter {
-//     type Result = String;
-//     fn visit_expr(&mut self, expr: &Expr) -> Self::Result {
-//         match expr.kind {
-//             ExprKind::Unary(expr, op) => {
-//                 let op_str = match op {
-//                     UnOp::Minus => "-",
-//                     UnOp::Bang => "!",
-//                 };
-//                 format!("({}{})", op_str, self.visit_expr(expr.as_ref()))
-//             }
-//             ExprKind::Binary(left, right, op) => {
-//                 let op_str = match op {
-//                     BinOp::Bang => "!",
-//                     BinOp::BangEqual => "!=",
-//                     BinOp::Equal => "=",
-//                     BinOp::EqualEqual => "==",
-//                     BinOp::Greater => ">",
-//                     BinOp::GreaterEqual => ">=",
-//                     BinOp::Less => "<",
-//                     BinOp::LessEqual => "<=",
-//                     BinOp::Plus => "+",
-//                     BinOp::Minus => "-",
-//                     BinOp::Star => "*",
-//                     BinOp::Slash => "/",
-//                 };
-//                 format!(
-//                     "( {} {} {} )",
-//                     op_str,
-//                     self.visit_expr(left.as_ref()),
-//                     self.visit_expr(right.as_ref())
-//                 )
-//             }
-//             ExprKind::Grouping(expr) => format!("(gr {})", self.visit_expr(expr.as_ref())),
-//             ExprKind::Literal(kind) => match kind {
-//                 LitKind::Nil => "nil".to_string(),
-//                 LitKind::True => "true".to_string(),
-//                 LitKind::False => "false".to_string(),
-//                 LitKind::Number(n) => n.to_string(),
-//                 LitKind::String(s) => format!("\"{s}\""),
-//             },
-//         }
-//     }
-// }
-//
-// #[cfg(test)]
-// mod test {
-//     use super::*;
-//
-//     #[test]
-//     fn test_printer() {
-//         let expr = ExprKind::Binary(
-//             ExprKind::Unary(ExprKind::Literal(LitKind::Number(123.)).into(), UnOp::Minus).into(),
-//             ExprKind::Grouping(ExprKind::Literal(LitKind::String("45.67".into())).into()).into(),
-//             BinOp::Star,
-//         );
-//         let mut printer = PrettyPrinter {};
-//         let repr = printer.visit_expr(&expr);
-//         assert_eq!(repr, "( * (-123) (gr \"45.67\") )");
-//     }
-// }
+    with_stack(|| match &expr.kind {
+        ExprKind::Literal(lit) => v.visit_literal(expr, lit),
+        ExprKind::Unary(inner, op) => v.visit_unary(expr, inner, op),
+        ExprKind::Binary(left, right, op) => v.visit_binary(expr, left, right, op),
+        ExprKind::Grouping(inner) => v.visit_grouping(expr, inner),
+        ExprKind::List(items) => v.visit_list(expr, items),
+        ExprKind::Map(entries) => v.visit_map(expr, entries),
+        ExprKind::Index(list, index) => v.visit_index(expr, list, index),
+        ExprKind::Slice(target, start, end) => {
+            v.visit_slice(expr, target, start.as_deref(), end.as_deref())
+        }
+        ExprKind::Variable(name) => v.visit_variable(expr, name),
+        ExprKind::Call(callee, args) => v.visit_call(expr, callee, args),
+        ExprKind::Spread(inner) => v.visit_spread(expr, inner),
+        ExprKind::Match(subject, arms, otherwise) => v.visit_match(expr, subject, arms, otherwise),
+    })
+}
+
+/// Visits the children of `expr` in source order and combines what they
+/// came to; what a `visit_*` method does unless a pass overrides it.
+pub fn walk_children<V>(v: &mut V, expr: &Expr) -> V::Output
+where
+    V: Visitor,
+{
+    let children = expr
+        .children()
+        .into_iter()
+        .map(|child| v.visit_expr(child))
+        .collect();
+    v.combine(expr, children)
+}
+
+/// Runs `f`, on a fresh stack segment if this one is nearly used up. A
+/// tree is as tall as the longest operator chain in the source, so every
+/// recursion over one goes through here instead of being capped.
+pub fn with_stack<R>(f: impl FnOnce() -> R) -> R {
+    // Far more than any one pass needs between two calls
+    const RED_ZONE: usize = 256 * 1024;
+    stacker::maybe_grow(RED_ZONE, 1024 * 1024, f)
+}
+
+/// A pass that rewrites the tree (constant folding, desugaring, ...).
+/// Folders take nodes by value and return their replacement; by default a
+/// node is rebuilt, keeping its id, from its folded children. Passes
+/// compose by feeding one folder's output to the next.
+pub trait Folder: Sized {
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_children(self, expr)
+    }
+}
+
+pub fn fold_children<F>(f: &mut F, mut expr: Expr) -> Expr
+where
+    F: Folder,
+{
+    let kind = expr.take_kind();
+    expr.kind = with_stack(|| match kind {
+        ExprKind::Literal(lit) => ExprKind::Literal(lit),
+        ExprKind::Unary(inner, op) => ExprKind::Unary(Box::new(f.fold_expr(*inner)), op),
+        ExprKind::Binary(left, right, op) => ExprKind::Binary(
+            Box::new(f.fold_expr(*left)),
+            Box::new(f.fold_expr(*right)),
+            op,
+        ),
+        ExprKind::Grouping(inner) => ExprKind::Grouping(Box::new(f.fold_expr(*inner))),
+        ExprKind::List(items) => {
+            ExprKind::List(items.into_iter().map(|e| f.fold_expr(e)).collect())
+        }
+        ExprKind::Map(entries) => ExprKind::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (f.fold_expr(k), f.fold_expr(v)))
+                .collect(),
+        ),
+        ExprKind::Index(list, index) => {
+            ExprKind::Index(Box::new(f.fold_expr(*list)), Box::new(f.fold_expr(*index)))
+        }
+        ExprKind::Slice(target, start, end) => ExprKind::Slice(
+            Box::new(f.fold_expr(*target)),
+            start.map(|e| Box::new(f.fold_expr(*e))),
+            end.map(|e| Box::new(f.fold_expr(*e))),
+        ),
+        ExprKind::Variable(name) => ExprKind::Variable(name),
+        ExprKind::Call(callee, args) => ExprKind::Call(
+            Box::new(f.fold_expr(*callee)),
+            args.into_iter().map(|e| f.fold_expr(e)).collect(),
+        ),
+        ExprKind::Spread(inner) => ExprKind::Spread(Box::new(f.fold_expr(*inner))),
+        ExprKind::Match(subject, arms, otherwise) => ExprKind::Match(
+            Box::new(f.fold_expr(*subject)),
+            arms.into_iter().map(|(p, e)| (p, f.fold_expr(e))).collect(),
+            Box::new(f.fold_expr(*otherwise)),
+        ),
+    });
+    expr.depth = expr.kind.depth();
+    expr
+}
+
+/// Prints an expression as a parenthesized tree, e.g. `( * (-123) (gr "45.67") )`.
+pub struct PrettyPrinter;
+
+impl PrettyPrinter {
+    pub fn print(expr: &Expr) -> String {
+        PrettyPrinter.visit_expr(expr)
+    }
+}
+
+impl Visitor for PrettyPrinter {
+    type Output = String;
+
+    // Every kind of node has its own visit
+    fn combine(&mut self, _expr: &Expr, _children: Vec<String>) -> String {
+        unreachable!()
+    }
+
+    fn visit_literal(&mut self, _expr: &Expr, lit: &LitKind) -> String {
+        match lit {
+            LitKind::String(s) => format!("\"{s}\""),
+            _ => lit.to_string(),
+        }
+    }
+
+    fn visit_unary(&mut self, _expr: &Expr, inner: &Expr, op: &UnOp) -> String {
+        format!("({op}{})", self.visit_expr(inner))
+    }
+
+    fn visit_binary(&mut self, _expr: &Expr, left: &Expr, right: &Expr, op: &BinOp) -> String {
+        format!(
+            "( {op} {} {} )",
+            self.visit_expr(left),
+            self.visit_expr(right)
+        )
+    }
+
+    fn visit_grouping(&mut self, _expr: &Expr, inner: &Expr) -> String {
+        format!("(gr {})", self.visit_expr(inner))
+    }
+
+    fn visit_list(&mut self, _expr: &Expr, items: &[Expr]) -> String {
+        let items: Vec<String> = items.iter().map(|e| self.visit_expr(e)).collect();
+        format!("[{}]", items.join(" "))
+    }
+
+    fn visit_map(&mut self, _expr: &Expr, entries: &[(Expr, Expr)]) -> String {
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|(k, v)| format!("{}: {}", self.visit_expr(k), self.visit_expr(v)))
+            .collect();
+        format!("{{{}}}", entries.join(" "))
+    }
+
+    fn visit_index(&mut self, _expr: &Expr, list: &Expr, index: &Expr) -> String {
+        format!(
+            "( [] {} {} )",
+            self.visit_expr(list),
+            self.visit_expr(index)
+        )
+    }
+
+    /// A bound that was left out prints as `_`.
+    fn visit_slice(
+        &mut self,
+        _expr: &Expr,
+        target: &Expr,
+        start: Option<&Expr>,
+        end: Option<&Expr>,
+    ) -> String {
+        let mut bound = |e: Option<&Expr>| e.map_or("_".to_string(), |e| self.visit_expr(e));
+        let (start, end) = (bound(start), bound(end));
+        format!("( [:] {} {start} {end} )", self.visit_expr(target))
+    }
+
+    fn visit_variable(&mut self, _expr: &Expr, name: &str) -> String {
+        name.to_string()
+    }
+
+    fn visit_call(&mut self, _expr: &Expr, callee: &Expr, args: &[Expr]) -> String {
+        let mut parts = vec![self.visit_expr(callee)];
+        parts.extend(args.iter().map(|e| self.visit_expr(e)));
+        format!("( call {} )", parts.join(" "))
+    }
+
+    fn visit_spread(&mut self, _expr: &Expr, inner: &Expr) -> String {
+        format!("(...{})", self.visit_expr(inner))
+    }
+
+    fn visit_match(
+        &mut self,
+        _expr: &Expr,
+        subject: &Expr,
+        arms: &[(Pattern, Expr)],
+        otherwise: &Expr,
+    ) -> String {
+        let mut parts = vec![self.visit_expr(subject)];
+        for (pattern, body) in arms {
+            parts.push(format!("{pattern} -> {}", self.visit_expr(body)));
+        }
+        parts.push(format!("else -> {}", self.visit_expr(otherwise)));
+        format!("( match {} )", parts.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::parse_tokens, scanner::scan_tokens};
+
+    fn print(source: &str) -> String {
+        PrettyPrinter::print(&parse_tokens(&scan_tokens(source).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_printer() {
+        assert_eq!(print("-123 * (\"45.67\")"), "( * (-123) (gr \"45.67\") )");
+        assert_eq!(print("1 < 2 == !true"), "( == ( < 1 2 ) (!true) )");
+        assert_eq!(print("1 - 2 - 3"), "( - ( - 1 2 ) 3 )");
+        assert_eq!(print("-2 ** 3 ** 4"), "(-( ** 2 ( ** 3 4 ) ))");
+        assert_eq!(
+            print("-[1, [], 2 + 3][0][1]"),
+            "(-( [] ( [] [1 [] ( + 2 3 )] 0 ) 1 ))"
+        );
+        assert_eq!(
+            print("\"abc\"[1:][:1 + 1]"),
+            "( [:] ( [:] \"abc\" 1 _ ) _ ( + 1 1 ) )"
+        );
+        assert_eq!(
+            print("-f(1, g())[0]"),
+            "(-( [] ( call f 1 ( call g ) ) 0 ))"
+        );
+        assert_eq!(
+            print("f(...[1], ...a..b)"),
+            "( call f (...[1]) (...( .. a b )) )"
+        );
+        assert_eq!(print("a ?? b == c ?? d"), "( ?? ( ?? a ( == b c ) ) d )");
+        assert_eq!(
+            print("match (x) { -1 -> nil; [a, {\"b\": _}] -> a; else -> 0 }"),
+            "( match x; -1 -> nil; [a, {\"b\": _}] -> a; else -> 0 )"
+        );
+    }
+
+    #[test]
+    fn test_numbers_ignore_locale() {
+        // Whatever LC_NUMERIC says, '.' is the decimal point and ',' is a token
+        let tokens = scan_tokens("1,5 2.5").unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(print("2.5"), "2.5");
+        assert_eq!(LitKind::Number(1234567.).to_string(), "1234567");
+
+        assert_eq!(format_number(123456.5, 2, ","), "123,456.50");
+        assert_eq!(format_number(-999.5, 0, "."), "-1.000");
+        assert_eq!(format_number(12., 3, "'"), "12.000");
+        assert_eq!(format_number(f32::NAN, 2, ","), "NaN");
+        assert_eq!(format_number(-0.001, 2, ","), "0.00");
+        assert_eq!(format_number(-0.4, 0, ""), "0");
+    }
+
+    #[test]
+    fn test_conversions() {
+        let value = LitKind::from(vec![Some(1.5), None]);
+        assert_eq!(value.to_string(), "[1.5, nil]");
+        let map = LitKind::from(HashMap::from([("k".to_string(), vec!["v"])]));
+        assert_eq!(map.to_string(), r#"{"k": ["v"]}"#);
+        assert_eq!(LitKind::from(true), LitKind::Boolean(true));
+
+        assert_eq!(f64::try_from(LitKind::from(2.5)).unwrap(), 2.5);
+        let strings: HashMap<String, Vec<String>> = map.try_into().unwrap();
+        assert_eq!(strings["k"], ["v"]);
+        let numbers: Result<Vec<f32>, _> = LitKind::from(vec!["1"]).try_into();
+        match numbers {
+            Err(LoxError::RuntimeError(e)) => {
+                assert_eq!(
+                    e.to_string(),
+                    "line 0, \"\": [E3002] expected a number, got a string"
+                )
+            }
+            other => panic!("expected a runtime error, got {other:?}"),
+        }
+        assert!(String::try_from(LitKind::Nil).is_err());
+    }
+
+    #[test]
+    fn test_folder() {
+        // Drops groupings and swaps the operands of every `+`.
+        struct Rewrite;
+        impl Folder for Rewrite {
+            fn fold_expr(&mut self, expr: Expr) -> Expr {
+                let mut expr = fold_children(self, expr);
+                match expr.take_kind() {
+                    ExprKind::Grouping(inner) => *inner,
+                    ExprKind::Binary(left, right, BinOp::Plus) => Expr::new(
+                        ExprKind::Binary(right, left, BinOp::Plus),
+                        expr.token.clone(),
+                    ),
+                    kind => {
+                        expr.kind = kind;
+                        expr
+                    }
+                }
+            }
+        }
+
+        let expr = parse_tokens(&scan_tokens("(1 + 2) * -(3 + 4)").unwrap()).unwrap();
+        let id = expr.id;
+        let folded = Rewrite.fold_expr(expr);
+        assert_eq!(
+            PrettyPrinter::print(&folded),
+            "( * ( + 2 1 ) (-( + 4 3 )) )"
+        );
+        assert_eq!(folded.id, id);
+    }
+
+    #[test]
+    fn test_default_walkers() {
+        // Counts the variables, looking through everything else
+        struct Variables;
+        impl Visitor for Variables {
+            type Output = usize;
+
+            fn combine(&mut self, _expr: &Expr, children: Vec<usize>) -> usize {
+                children.into_iter().sum()
+            }
+
+            fn visit_variable(&mut self, _expr: &Expr, _name: &str) -> usize {
+                1
+            }
+        }
+
+        let source = "[a, -(b + 1)][f(...c)] ?? match (d) { [e] -> e; else -> {g: 2} }";
+        let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+        assert_eq!(Variables.visit_expr(&expr), 7);
+    }
+
+    #[test]
+    fn test_ids_and_spans() {
+        let source = "1 +\n -( 2 )";
+        let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+        let ExprKind::Binary(left, right, _) = &expr.kind else {
+            panic!("expected a binary expression");
+        };
+        let ExprKind::Unary(group, _) = &right.kind else {
+            panic!("expected a unary expression");
+        };
+        assert!(left.id < group.id && group.id < right.id && right.id < expr.id);
+        assert_eq!(&source[group.span.start..group.span.end], "( 2 )");
+        assert_eq!((right.span.start, right.span.column), (5, 2));
+        assert_eq!(&source[expr.span.start..expr.span.end], source);
+    }
+}
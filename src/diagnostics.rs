@@ -0,0 +1,215 @@
+use anyhow::{anyhow, Result};
+use derive_more::Display;
+use itertools::Itertools;
+
+use crate::errors::{ErrorCode, GenericError, LoxError};
+
+/// Lints that can be switched on and off with `-W<name>` and `-Wno-<name>`.
+/// `-Wdeprecated` switches every deprecation at once.
+const LINTS: &[(&str, ErrorCode)] = &[
+    ("division-by-zero", ErrorCode::DivisionByConstantZero),
+    ("string-number-compare", ErrorCode::StringComparedWithNumber),
+    ("imprecise-number", ErrorCode::ImpreciseNumber),
+];
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    #[display("warning")]
+    Warning,
+    #[display("error")]
+    Error,
+}
+
+/// Something worth telling the user about; the code, span and message live
+/// in the error itself.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Shown in front of the message, e.g. "warning" or "parse error".
+    pub label: &'static str,
+    pub error: GenericError,
+}
+
+/// Which warnings to report, set from the command line.
+#[derive(Debug, Default, Clone)]
+pub struct WarningOptions {
+    disabled: Vec<ErrorCode>,
+    /// `-Werror`: every reported warning fails the run.
+    as_errors: bool,
+}
+
+impl WarningOptions {
+    /// Applies one `-W` flag, given without the `-W`.
+    pub fn set(&mut self, flag: &str) -> Result<()> {
+        let (name, enable) = match flag.strip_prefix("no-") {
+            Some(name) => (name, false),
+            None => (flag, true),
+        };
+        if name == "error" {
+            self.as_errors = enable;
+            return Ok(());
+        }
+        let codes: Vec<ErrorCode> = LINTS
+            .iter()
+            .filter(|(lint, code)| match name {
+                "deprecated" => code.is_deprecation(),
+                _ => *lint == name,
+            })
+            .map(|(_, code)| *code)
+            .collect();
+        if codes.is_empty() {
+            return Err(anyhow!("unknown warning -W{flag}"));
+        }
+        self.disabled.retain(|c| !codes.contains(c));
+        if !enable {
+            self.disabled.extend(codes);
+        }
+        Ok(())
+    }
+}
+
+/// Everything worth reporting about one piece of source: errors from every
+/// phase and the warnings `options` let through. The CLI, the REPL and the
+/// self test all report through this, so they show the same thing.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    options: WarningOptions,
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new(options: &WarningOptions) -> Self {
+        Self {
+            options: options.clone(),
+            items: vec![],
+        }
+    }
+
+    pub fn warn(&mut self, error: GenericError) {
+        if self.options.disabled.contains(&error.code) {
+            return;
+        }
+        let (severity, label) = match self.options.as_errors {
+            true => (Severity::Error, "error"),
+            false => (Severity::Warning, "warning"),
+        };
+        self.items.push(Diagnostic {
+            severity,
+            label,
+            error,
+        });
+    }
+
+    /// Records an error in the source. I/O errors have no place in it, so
+    /// they are reported by whoever failed to read it.
+    pub fn error(&mut self, error: LoxError) {
+        let (label, error) = error
+            .into_parts()
+            .expect("only errors in the source are diagnostics");
+        self.items.push(Diagnostic {
+            severity: Severity::Error,
+            label,
+            error,
+        });
+    }
+
+    /// The diagnostics in the order they appear in the source.
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter().sorted_by_key(|d| d.error.span.start)
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.items.iter().filter(|d| d.severity == severity).count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The full report, every diagnostic rendered against `source`.
+    pub fn render(&self, source: &str) -> String {
+        self.iter()
+            .map(|d| d.error.render(d.label, source))
+            .join("\n")
+    }
+
+    /// The full report as JSON, one diagnostic per line.
+    pub fn to_json(&self, file: &str) -> String {
+        self.iter()
+            .map(|d| d.error.to_json(&d.severity.to_string(), file))
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lint::lint, parser::parse_tokens, scanner::scan_tokens};
+
+    fn diagnostics(flags: &[&str], source: &str) -> Diagnostics {
+        let mut options = WarningOptions::default();
+        for flag in flags {
+            options.set(flag).unwrap();
+        }
+        let mut diagnostics = Diagnostics::new(&options);
+        for warning in lint(&parse_tokens(&scan_tokens(source).unwrap()).unwrap()) {
+            diagnostics.warn(warning);
+        }
+        diagnostics
+    }
+
+    #[test]
+    fn test_flags() {
+        let source = "1 / 0 + (\"0\" < 1)";
+        assert_eq!(diagnostics(&[], source).count(Severity::Warning), 2);
+        assert!(!diagnostics(&[], source).has_errors());
+
+        let only = diagnostics(&["no-division-by-zero"], source);
+        let codes: Vec<ErrorCode> = only.iter().map(|d| d.error.code).collect();
+        assert_eq!(codes, vec![ErrorCode::StringComparedWithNumber]);
+
+        let again = diagnostics(&["no-division-by-zero", "division-by-zero"], source);
+        assert_eq!(again.count(Severity::Warning), 2);
+        assert_eq!(diagnostics(&["error"], source).count(Severity::Error), 2);
+        assert!(!diagnostics(&["error", "no-error"], source).has_errors());
+        assert!(WarningOptions::default().set("no-such-lint").is_err());
+    }
+
+    #[test]
+    fn test_deprecations() {
+        let source = "16777217 + 0.5";
+        let all = diagnostics(&[], source);
+        let codes: Vec<ErrorCode> = all.iter().map(|d| d.error.code).collect();
+        assert_eq!(codes, vec![ErrorCode::ImpreciseNumber]);
+        assert!(diagnostics(&["no-deprecated"], source).is_empty());
+        assert!(diagnostics(&["no-imprecise-number"], source).is_empty());
+        assert!(diagnostics(&["error"], source).has_errors());
+    }
+
+    #[test]
+    fn test_report_is_in_source_order() {
+        // The runtime error comes last, but is reported where it is
+        let source = "(\"a\" < 1) + (1 / 0)";
+        let mut diagnostics = diagnostics(&[], source);
+        diagnostics.error(LoxError::new_runtime(
+            &scan_tokens(source).unwrap()[0],
+            ErrorCode::IncompatibleOperands,
+            "incompatible types",
+        ));
+        let codes: Vec<String> = diagnostics
+            .iter()
+            .map(|d| d.error.code.to_string())
+            .collect();
+        assert_eq!(codes, vec!["E3001", "W0002", "W0001"]);
+        assert!(diagnostics.has_errors());
+
+        let report = diagnostics.render(source);
+        assert!(report.starts_with("runtime error[E3001]: incompatible types"));
+        assert_eq!(report.matches("warning[").count(), 2);
+        assert_eq!(diagnostics.to_json("a.lox").lines().count(), 3);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::ast::{BinOp, Expr, LitKind, Pattern, UnOp, Visitor};
+
+/// Renders the syntax tree as a Graphviz graph, e.g.
+/// `jilox --ast-dot file.lox | dot -Tpng > ast.png`.
+/// Each node is labeled with its operator or literal and its 1-based source line.
+#[derive(Default)]
+pub struct AstDotExporter {
+    lines: Vec<String>,
+    next_id: usize,
+}
+
+impl AstDotExporter {
+    pub fn export(expr: &Expr) -> String {
+        let mut exporter = Self::default();
+        exporter.visit_expr(expr);
+        let mut out = String::from("digraph ast {\n    node [shape=box];\n");
+        for line in exporter.lines {
+            out += &format!("    {line}\n");
+        }
+        out += "}";
+        out
+    }
+
+    /// Adds a node and the edges to its already exported children,
+    /// returning the new node's id.
+    fn node(&mut self, expr: &Expr, label: &str, children: &[usize]) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        let label = format!("{label}\nline {}", expr.token.line + 1);
+        self.lines.push(format!("n{id} [label={}];", quote(&label)));
+        for child in children {
+            self.lines.push(format!("n{id} -> n{child};"));
+        }
+        id
+    }
+}
+
+fn quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+impl Visitor for AstDotExporter {
+    type Output = usize;
+
+    // Every kind of node has its own visit
+    fn combine(&mut self, _expr: &Expr, _children: Vec<usize>) -> usize {
+        unreachable!()
+    }
+
+    fn visit_literal(&mut self, expr: &Expr, lit: &LitKind) -> usize {
+        let label = match lit {
+            LitKind::String(s) => format!("\"{s}\""),
+            _ => lit.to_string(),
+        };
+        self.node(expr, &label, &[])
+    }
+
+    fn visit_unary(&mut self, expr: &Expr, inner: &Expr, op: &UnOp) -> usize {
+        let inner = self.visit_expr(inner);
+        self.node(expr, &op.to_string(), &[inner])
+    }
+
+    fn visit_binary(&mut self, expr: &Expr, left: &Expr, right: &Expr, op: &BinOp) -> usize {
+        let left = self.visit_expr(left);
+        let right = self.visit_expr(right);
+        self.node(expr, &op.to_string(), &[left, right])
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr, inner: &Expr) -> usize {
+        let inner = self.visit_expr(inner);
+        self.node(expr, "group", &[inner])
+    }
+
+    fn visit_list(&mut self, expr: &Expr, items: &[Expr]) -> usize {
+        let items: Vec<usize> = items.iter().map(|e| self.visit_expr(e)).collect();
+        self.node(expr, "list", &items)
+    }
+
+    fn visit_map(&mut self, expr: &Expr, entries: &[(Expr, Expr)]) -> usize {
+        let children: Vec<usize> = entries
+            .iter()
+            .flat_map(|(k, v)| [k, v])
+            .map(|e| self.visit_expr(e))
+            .collect();
+        self.node(expr, "map", &children)
+    }
+
+    fn visit_index(&mut self, expr: &Expr, list: &Expr, index: &Expr) -> usize {
+        let list = self.visit_expr(list);
+        let index = self.visit_expr(index);
+        self.node(expr, "index", &[list, index])
+    }
+
+    fn visit_slice(
+        &mut self,
+        expr: &Expr,
+        target: &Expr,
+        start: Option<&Expr>,
+        end: Option<&Expr>,
+    ) -> usize {
+        let children: Vec<usize> = [Some(target), start, end]
+            .into_iter()
+            .flatten()
+            .map(|e| self.visit_expr(e))
+            .collect();
+        self.node(expr, "slice", &children)
+    }
+
+    fn visit_variable(&mut self, expr: &Expr, name: &str) -> usize {
+        self.node(expr, name, &[])
+    }
+
+    fn visit_call(&mut self, expr: &Expr, callee: &Expr, args: &[Expr]) -> usize {
+        let children: Vec<usize> = std::iter::once(callee)
+            .chain(args)
+            .map(|e| self.visit_expr(e))
+            .collect();
+        self.node(expr, "call", &children)
+    }
+
+    fn visit_spread(&mut self, expr: &Expr, inner: &Expr) -> usize {
+        let child = self.visit_expr(inner);
+        self.node(expr, "...", &[child])
+    }
+
+    /// The arms are labelled with their patterns, in order, so the edges
+    /// can be matched up with them.
+    fn visit_match(
+        &mut self,
+        expr: &Expr,
+        subject: &Expr,
+        arms: &[(Pattern, Expr)],
+        otherwise: &Expr,
+    ) -> usize {
+        let children: Vec<usize> = std::iter::once(subject)
+            .chain(arms.iter().map(|(_, body)| body))
+            .chain([otherwise])
+            .map(|e| self.visit_expr(e))
+            .collect();
+        let patterns: Vec<String> = arms.iter().map(|(p, _)| p.to_string()).collect();
+        let label = format!("match\n{} | else", patterns.join(" | "));
+        self.node(expr, &label, &children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse_tokens, scanner::scan_tokens};
+
+    #[test]
+    fn test_export() {
+        let expr = parse_tokens(&scan_tokens("-\"a\" * (2)").unwrap()).unwrap();
+        let want = r#"digraph ast {
+    node [shape=box];
+    n0 [label="\"a\"\nline 1"];
+    n1 [label="-\nline 1"];
+    n1 -> n0;
+    n2 [label="2\nline 1"];
+    n3 [label="group\nline 1"];
+    n3 -> n2;
+    n4 [label="*\nline 1"];
+    n4 -> n1;
+    n4 -> n3;
+}"#;
+        assert_eq!(AstDotExporter::export(&expr), want);
+    }
+}
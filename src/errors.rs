@@ -1,40 +1,339 @@
+use std::{convert::Infallible, io};
+
+use derive_more::Display;
 use thiserror::Error;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    json::escape,
+    scanner::{Span, Token},
+};
+
+/// Stable identifiers for every diagnostic, so tests and editors can match
+/// on codes instead of message text. Codes are never reused or renumbered:
+/// E1xxx scanning, E2xxx parsing, E3xxx runtime, W0xxx lints, and W1xxx
+/// deprecations: code that will mean something else in a future version.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    #[display("E1001")]
+    UnterminatedString,
+    #[display("E1002")]
+    UnexpectedCharacter,
+    #[display("E1003")]
+    InvalidNumber,
+
+    #[display("E2001")]
+    UnclosedGroup,
+    #[display("E2002")]
+    ExpectedExpression,
+    #[display("E2003")]
+    TooDeeplyNested,
+    #[display("E2004")]
+    UnclosedBracket,
+    #[display("E2005")]
+    UnclosedBrace,
+    #[display("E2006")]
+    ExpectedColon,
+    #[display("E2007")]
+    ExpectedToken,
+    #[display("E2008")]
+    MissingElseArm,
+    #[display("E2009")]
+    InvalidPattern,
+    #[display("E2010")]
+    DuplicateBinding,
+
+    #[display("E3001")]
+    IncompatibleOperands,
+    #[display("E3002")]
+    InvalidOperand,
+    #[display("E3003")]
+    DivisionByZero,
+    #[display("E3004")]
+    NotAnInteger,
+    #[display("E3005")]
+    IndexOutOfRange,
+    #[display("E3006")]
+    KeyNotFound,
+    #[display("E3007")]
+    WrongArity,
+    #[display("E3008")]
+    UndefinedVariable,
+    #[display("E3009")]
+    NotCallable,
+    #[display("E3010")]
+    InputFailed,
+    #[display("E3011")]
+    AssertionFailed,
+    /// Raised by the script itself, with `error()`.
+    #[display("E3012")]
+    ScriptError,
+    #[display("E3013")]
+    InvalidFormat,
+    #[display("E3014")]
+    InvalidJson,
+    /// The script touched the file system without `--allow-fs`.
+    #[display("E3015")]
+    FsNotAllowed,
+    #[display("E3016")]
+    FsFailed,
+    #[display("E3017")]
+    InvalidCsv,
+    #[display("E3018")]
+    InvalidRegex,
+
+    /// A bug in jilox itself, never in the script.
+    #[display("E9001")]
+    InternalError,
+
+    #[display("W0001")]
+    DivisionByConstantZero,
+    #[display("W0002")]
+    StringComparedWithNumber,
 
-use crate::scanner::Token;
+    #[display("W1001")]
+    ImpreciseNumber,
+}
+
+impl ErrorCode {
+    pub fn is_runtime(self) -> bool {
+        use ErrorCode::*;
+        matches!(
+            self,
+            IncompatibleOperands
+                | InvalidOperand
+                | DivisionByZero
+                | NotAnInteger
+                | IndexOutOfRange
+                | KeyNotFound
+                | WrongArity
+                | UndefinedVariable
+                | NotCallable
+                | InputFailed
+                | AssertionFailed
+                | ScriptError
+                | InvalidFormat
+                | InvalidJson
+                | FsNotAllowed
+                | FsFailed
+                | InvalidCsv
+                | InvalidRegex
+        )
+    }
+
+    pub fn is_deprecation(self) -> bool {
+        matches!(self, ErrorCode::ImpreciseNumber)
+    }
+}
 
-#[derive(Error, Debug, Default)]
-#[error("line {line}, \"{lexeme}\": {message}")]
+#[derive(Error, Debug)]
+#[error("line {line}, \"{lexeme}\": [{code}] {message}")]
 pub struct GenericError {
-    line: u32,
+    pub line: u32,
     lexeme: String,
+    pub code: ErrorCode,
     message: String,
+    pub span: Span,
+    notes: Vec<String>,
 }
 
 impl GenericError {
-    pub fn new(t: &Token, message: &str) -> Self {
+    pub fn new(t: &Token, code: ErrorCode, message: &str) -> Self {
+        Self::at(t.line, &t.lexeme, t.span, code, message)
+    }
+
+    /// For errors that don't have a token to point at, e.g. from the scanner.
+    pub fn at(line: u32, lexeme: &str, span: Span, code: ErrorCode, message: &str) -> Self {
+        Self {
+            line,
+            lexeme: lexeme.to_string(),
+            code,
+            message: message.to_string(),
+            span,
+            notes: vec![],
+        }
+    }
+
+    /// Moves the error to `t`, keeping its code, message and notes.
+    pub fn relocate(self, t: &Token) -> Self {
         Self {
             line: t.line,
             lexeme: t.lexeme.clone(),
-            message: message.to_string(),
+            span: t.span,
+            ..self
         }
     }
+
+    pub fn with_note(mut self, note: impl ToString) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Renders the error rustc-style: the message, then the source line it
+    /// points at with the offending span underlined, then any notes.
+    /// `source` must be the text the error's token was scanned from.
+    pub fn render(&self, severity: &str, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let end = self.span.end.clamp(start, line_end);
+
+        // NOTE: lines are counted from 0 internally, but editors count from 1
+        let number = (source[..start].matches('\n').count() + 1).to_string();
+        let gutter = " ".repeat(number.len());
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        // Keep tabs so the caret lines up with the text above it
+        let pad: String = source[line_start..start]
+            .graphemes(true)
+            .map(|g| if g == "\t" { '\t' } else { ' ' })
+            .collect();
+        let width = source[start..end].graphemes(true).count().max(1);
+
+        let mut out = format!(
+            "{severity}[{}]: {}\n{gutter}--> line {number}, column {}\n{gutter} |\n{number} | {text}\n{gutter} | {pad}{}",
+            self.code,
+            self.message,
+            pad.chars().count() + 1,
+            "^".repeat(width)
+        );
+        for note in &self.notes {
+            out += &format!("\n{gutter} = note: {note}");
+        }
+        out
+    }
+
+    /// One-line JSON for tools: code, severity, message, file, 1-based line
+    /// and column, and the byte span. `severity` is "error" or "warning".
+    pub fn to_json(&self, severity: &str, file: &str) -> String {
+        let notes: Vec<String> = self.notes.iter().map(|n| escape(n)).collect();
+        format!(
+            "{{\"code\": \"{}\", \"severity\": {}, \"message\": {}, \"file\": {}, \"line\": {}, \"column\": {}, \"span\": [{}, {}], \"notes\": [{}]}}",
+            self.code,
+            escape(severity),
+            escape(&self.message),
+            escape(file),
+            self.line + 1,
+            self.span.column,
+            self.span.start,
+            self.span.end,
+            notes.join(", ")
+        )
+    }
 }
 
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum LoxError {
+    #[error("Scan error: {0}")]
+    ScanError(GenericError),
+
     #[error("Parse error: {0}")]
     ParseError(GenericError),
 
     #[error("Runtime error: {0}")]
     RuntimeError(GenericError),
+
+    #[error("{path}: {source}")]
+    IoError {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Not an error, but `exit()` unwinding the interpreter on its way out.
+    #[error("exit({0})")]
+    Exit(i32),
 }
 
 impl LoxError {
     #[inline]
-    pub fn new_runtime(t: &Token, msg: &str) -> Self {
-        Self::RuntimeError(GenericError::new(t, msg))
+    pub fn new_runtime(t: &Token, code: ErrorCode, msg: &str) -> Self {
+        Self::RuntimeError(GenericError::new(t, code, msg))
     }
-    pub fn new_parse(t: &Token, msg: &str) -> Self {
-        Self::ParseError(GenericError::new(t, msg))
+    pub fn new_parse(t: &Token, code: ErrorCode, msg: &str) -> Self {
+        Self::ParseError(GenericError::new(t, code, msg))
+    }
+
+    /// The phase that failed, as shown in front of the message, and the
+    /// error. `None` for errors that don't point into the source.
+    pub fn into_parts(self) -> Option<(&'static str, GenericError)> {
+        match self {
+            Self::ScanError(e) => Some(("scan error", e)),
+            Self::ParseError(e) => Some(("parse error", e)),
+            Self::RuntimeError(e) => Some(("runtime error", e)),
+            Self::IoError { .. } | Self::Exit(_) => None,
+        }
+    }
+}
+
+// A conversion that can't fail, like a value into itself, fits wherever
+// one returning a LoxError is expected
+impl From<Infallible> for LoxError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpreter::Interpreter, parser::parse_tokens, scanner::scan_tokens};
+
+    fn render(err: LoxError, source: &str) -> String {
+        let (phase, e) = err.into_parts().unwrap();
+        e.render(phase, source)
+    }
+
+    #[test]
+    fn test_codes() {
+        let code = |source: &str| {
+            let err = scan_tokens(source)
+                .and_then(|tokens| parse_tokens(&tokens))
+                .and_then(|expr| Interpreter::new().interpret(&expr))
+                .unwrap_err();
+            err.into_parts().unwrap().1.code
+        };
+        assert_eq!(code("\"abc"), ErrorCode::UnterminatedString);
+        assert_eq!(code("1 $"), ErrorCode::UnexpectedCharacter);
+        assert_eq!(code("1."), ErrorCode::InvalidNumber);
+        assert_eq!(code("(1"), ErrorCode::UnclosedGroup);
+        assert_eq!(code("1 +"), ErrorCode::ExpectedExpression);
+        assert_eq!(code("1 + nil"), ErrorCode::IncompatibleOperands);
+        assert_eq!(code("-\"a\""), ErrorCode::InvalidOperand);
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "1 +\n\t(2 * \"two\")";
+        let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+        let err = Interpreter::new().interpret(&expr).unwrap_err();
+        let want = "runtime error[E3001]: incompatible types
+ --> line 2, column 5
+  |
+2 | \t(2 * \"two\")
+  | \t   ^
+  = note: left operand is a number, right operand is a string";
+        assert_eq!(render(err, source), want);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let source = "1 +\n\t(2 * \"two\")";
+        let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+        let err = Interpreter::new().interpret(&expr).unwrap_err();
+        let (_, e) = err.into_parts().unwrap();
+        assert_eq!(
+            e.to_json("error", "a\\b.lox"),
+            r#"{"code": "E3001", "severity": "error", "message": "incompatible types", "file": "a\\b.lox", "line": 2, "column": 5, "span": [8, 9], "notes": ["left operand is a number, right operand is a string"]}"#
+        );
+    }
+
+    #[test]
+    fn test_render_underlines_span() {
+        let source = "(1 + 2";
+        let err = parse_tokens(&scan_tokens(source).unwrap()).unwrap_err();
+        assert!(render(err, source).ends_with("1 | (1 + 2\n  | ^"));
     }
 }
@@ -1,44 +1,768 @@
+use std::{cell::Cell, collections::HashMap, time::Instant};
+
 use crate::{
-    ast::{BinaryEval, Expr, ExprKind, LitKind, UnaryEval, Visitor},
-    errors::LoxError,
+    ast::{
+        to_index, walk_expr, BinOp, BinaryEval, Expr, ExprKind, LitKind, LoxList, LoxMap, Pattern,
+        UnOp, UnaryEval, Visitor,
+    },
+    errors::{ErrorCode, GenericError, LoxError},
+    natives::{self, FromArgs, NativeFunction, Rng},
+    scanner::{Span, Token},
 };
 
+thread_local! {
+    /// The line and token span of the innermost node being evaluated.
+    static CURRENT: Cell<Option<(u32, Span)>> = const { Cell::new(None) };
+}
+
+/// Where in the script the interpreter on this thread is, `None` outside
+/// of `interpret`. A panic hook uses this to point at the script that
+/// triggered a bug.
+pub fn current_location() -> Option<(u32, Span)> {
+    CURRENT.get()
+}
+
 pub struct Interpreter {
-    pub result: Result<LitKind, LoxError>,
+    /// Let `x / 0` evaluate to an infinity or NaN, as IEEE 754 says,
+    /// instead of failing with a division by zero error.
+    pub ieee_division: bool,
+    /// Names visible everywhere; so far only the native functions.
+    globals: HashMap<String, LitKind>,
+    /// What the `match` arms being evaluated bound, innermost last. These
+    /// shadow the globals.
+    scopes: Vec<HashMap<String, LitKind>>,
+    /// Where `random()` gets its numbers.
+    pub rng: Rng,
+    /// What `args()` returns: the command line after the script's name.
+    pub args: Vec<String>,
+    /// The status the script asked to exit with, once it has unwound.
+    pub exit_code: Option<i32>,
+    /// When the interpreter was created; `elapsed()` counts from here.
+    pub started: Instant,
+    /// Let scripts look at the file system, e.g. with `listDir()`.
+    pub allow_fs: bool,
 }
 
-impl Visitor for Interpreter {
-    fn visit_expr(&mut self, expr: &Expr) {
-        self.result = visit_helper(self, expr);
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-fn visit_helper(intr: &mut Interpreter, expr: &Expr) -> Result<LitKind, LoxError> {
-    match &expr.kind {
-        ExprKind::Binary(l, r, op) => {
-            let left = visit_helper(intr, l)?;
-            let right = visit_helper(intr, r)?;
-            let err = LoxError::new_parse(&expr.token, "incompatible types");
-            Ok(match (left, right) {
-                (LitKind::Number(a), LitKind::Number(b)) => {
-                    LitKind::Number(op.bin_eval(a, b).ok_or(err)?)
-                }
-                (LitKind::String(a), LitKind::String(b)) => {
-                    LitKind::String(op.bin_eval(a, b).ok_or(err)?)
+impl Interpreter {
+    pub fn new() -> Self {
+        let mut interpreter = Self {
+            ieee_division: false,
+            globals: HashMap::new(),
+            scopes: Vec::new(),
+            rng: Rng::from_time(),
+            args: Vec::new(),
+            exit_code: None,
+            started: Instant::now(),
+            allow_fs: false,
+        };
+        for native in natives::natives() {
+            interpreter.define(&native.name.clone(), LitKind::Native(native));
+        }
+        for (name, value) in natives::CONSTANTS {
+            interpreter.define(name, LitKind::Number(*value));
+        }
+        interpreter
+    }
+
+    /// Defines, or redefines, a global.
+    pub fn define(&mut self, name: &str, value: LitKind) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    /// Defines a global function calling `func` with its arguments
+    /// converted to `A`, e.g. `|(n, s): (f64, String)|`, which also fixes
+    /// how many it takes. Failed conversions, and errors made with
+    /// `natives::native_error`, are reported at the call.
+    pub fn register_fn<A, R>(
+        &mut self,
+        name: &str,
+        func: impl Fn(A) -> Result<R, LoxError> + 'static,
+    ) where
+        A: FromArgs,
+        R: Into<LitKind>,
+    {
+        let native = NativeFunction::new(name, A::ARITY, move |_, args| {
+            func(A::from_args(args)?).map(Into::into)
+        });
+        self.define(name, LitKind::Native(native));
+    }
+
+    pub fn interpret(&mut self, expr: &Expr) -> Result<LitKind, LoxError> {
+        self.visit_expr(expr)
+    }
+}
+
+/// `index` is shown as written, e.g. `2` or `1:4` for a slice.
+fn out_of_range(t: &Token, target: &LitKind, index: &str, len: usize) -> LoxError {
+    let (what, unit) = match target {
+        LitKind::String(_) => ("string", "characters"),
+        LitKind::Range(_) => ("range", "numbers"),
+        _ => ("list", "elements"),
+    };
+    LoxError::RuntimeError(
+        GenericError::new(
+            t,
+            ErrorCode::IndexOutOfRange,
+            &format!("{what} index out of range"),
+        )
+        .with_note(format!(
+            "the index is {index}, but the {what} has {len} {unit}"
+        )),
+    )
+}
+
+/// Bitwise operators see numbers as 32-bit two's complement integers, so
+/// their operands have to be whole numbers in that range.
+fn to_int(t: &Token, n: f32) -> Result<i32, LoxError> {
+    if n.fract() == 0.0 && (-2_147_483_648.0..2_147_483_648.0).contains(&n) {
+        return Ok(n as i32);
+    }
+    Err(LoxError::RuntimeError(
+        GenericError::new(
+            t,
+            ErrorCode::NotAnInteger,
+            "bitwise operand is not a 32-bit integer",
+        )
+        .with_note(format!("the operand is {n}")),
+    ))
+}
+
+impl Visitor for Interpreter {
+    type Output = Result<LitKind, LoxError>;
+
+    fn visit_expr(&mut self, expr: &Expr) -> Self::Output {
+        let outer = CURRENT.replace(Some((expr.token.line, expr.token.span)));
+        let value = walk_expr(self, expr);
+        CURRENT.set(outer);
+        value
+    }
+
+    // Every kind of node has its own visit
+    fn combine(&mut self, _expr: &Expr, _children: Vec<Self::Output>) -> Self::Output {
+        unreachable!()
+    }
+
+    fn visit_literal(&mut self, _expr: &Expr, lit: &LitKind) -> Self::Output {
+        Ok(lit.clone())
+    }
+
+    fn visit_unary(&mut self, expr: &Expr, inner: &Expr, op: &UnOp) -> Self::Output {
+        let err =
+            LoxError::new_runtime(&expr.token, ErrorCode::InvalidOperand, "invalid operation");
+        Ok(match (op, self.visit_expr(inner)?) {
+            (UnOp::Bang, value) => LitKind::Boolean(!value.is_truthy()),
+            (UnOp::Tilde, LitKind::Number(n)) => {
+                LitKind::Number(op.unary_eval(to_int(&expr.token, n)?).ok_or(err)? as f32)
+            }
+            (_, LitKind::Number(n)) => LitKind::Number(op.unary_eval(n).ok_or(err)?),
+            _ => return Err(err),
+        })
+    }
+
+    fn visit_binary(&mut self, expr: &Expr, left: &Expr, right: &Expr, op: &BinOp) -> Self::Output {
+        let left = self.visit_expr(left)?;
+        if let BinOp::QuestionQuestion = op {
+            return match left {
+                LitKind::Nil => self.visit_expr(right),
+                left => Ok(left),
+            };
+        }
+        let right = self.visit_expr(right)?;
+        match op {
+            BinOp::EqualEqual => return Ok(LitKind::Boolean(left == right)),
+            BinOp::BangEqual => return Ok(LitKind::Boolean(left != right)),
+            _ => (),
+        }
+        let note = format!(
+            "left operand is a {}, right operand is a {}",
+            left.type_name(),
+            right.type_name()
+        );
+        let err = LoxError::RuntimeError(
+            GenericError::new(
+                &expr.token,
+                ErrorCode::IncompatibleOperands,
+                "incompatible types",
+            )
+            .with_note(note),
+        );
+        Ok(match (left, right) {
+            (LitKind::Number(_), LitKind::Number(b))
+                if b == 0.0
+                    && matches!(op, BinOp::Slash | BinOp::Percent)
+                    && !self.ieee_division =>
+            {
+                return Err(LoxError::new_runtime(
+                    &expr.token,
+                    ErrorCode::DivisionByZero,
+                    "division by zero",
+                ));
+            }
+            (LitKind::Number(a), LitKind::Number(b)) if op.is_bitwise() => {
+                let (a, b) = (to_int(&expr.token, a)?, to_int(&expr.token, b)?);
+                if matches!(op, BinOp::LessLess | BinOp::GreaterGreater) && !(0..32).contains(&b) {
+                    return Err(LoxError::new_runtime(
+                        &expr.token,
+                        ErrorCode::NotAnInteger,
+                        "shift amount must be between 0 and 31",
+                    ));
                 }
-                (LitKind::Nil, LitKind::Nil) => LitKind::Nil,
-                _ => return Err(err),
-            })
-        }
-        ExprKind::Grouping(ex) => visit_helper(intr, ex),
-        ExprKind::Unary(ex, op) => {
-            let err = LoxError::new_parse(&expr.token, "invalid operation");
-            Ok(match visit_helper(intr, ex)? {
-                LitKind::Boolean(b) => LitKind::Boolean(op.unary_eval(b).ok_or(err)?),
-                LitKind::Number(n) => LitKind::Number(op.unary_eval(n).ok_or(err)?),
-                _ => return Err(err),
-            })
-        }
-        ExprKind::Literal(lit) => Ok(lit.clone()),
+                op.bin_eval(a, b).ok_or(err)?
+            }
+            (LitKind::Number(a), LitKind::Number(b)) => op.bin_eval(a, b).ok_or(err)?,
+            (LitKind::String(a), LitKind::String(b)) => op.bin_eval(a, b).ok_or(err)?,
+            // "scone" + 4 stringifies the number, in either order
+            (LitKind::String(a), b @ LitKind::Number(_)) if matches!(op, BinOp::Plus) => {
+                LitKind::String(a + &b.to_string())
+            }
+            (a @ LitKind::Number(_), LitKind::String(b)) if matches!(op, BinOp::Plus) => {
+                LitKind::String(a.to_string() + &b)
+            }
+            _ => return Err(err),
+        })
+    }
+
+    fn visit_list(&mut self, _expr: &Expr, items: &[Expr]) -> Self::Output {
+        let items = items
+            .iter()
+            .map(|e| self.visit_expr(e))
+            .collect::<Result<_, _>>()?;
+        Ok(LitKind::List(LoxList::new(items)))
+    }
+
+    fn visit_map(&mut self, _expr: &Expr, entries: &[(Expr, Expr)]) -> Self::Output {
+        let mut map = LoxMap::default();
+        for (key_expr, value) in entries {
+            let key = self.visit_expr(key_expr)?;
+            let value = self.visit_expr(value)?;
+            let note = format!("keys are strings or numbers, this is a {}", key.type_name());
+            if !map.insert(key, value) {
+                return Err(LoxError::RuntimeError(
+                    GenericError::new(
+                        &key_expr.token,
+                        ErrorCode::InvalidOperand,
+                        "invalid map key",
+                    )
+                    .with_note(note),
+                ));
+            }
+        }
+        Ok(LitKind::Map(map))
+    }
+
+    /// Strings are indexed by code point, so `"héllo"[1]` is `"é"`.
+    fn visit_index(&mut self, expr: &Expr, target: &Expr, index: &Expr) -> Self::Output {
+        let target = self.visit_expr(target)?;
+        let index = self.visit_expr(index)?;
+        let value = match (&target, &index) {
+            (LitKind::Map(map), _) => map.get(&index).cloned().ok_or_else(|| {
+                LoxError::RuntimeError(
+                    GenericError::new(&expr.token, ErrorCode::KeyNotFound, "key not found")
+                        .with_note(format!("the map has no key {index}")),
+                )
+            })?,
+            (LitKind::Range(range), &LitKind::Number(n)) => {
+                LitKind::Number(range.get(n).ok_or_else(|| {
+                    out_of_range(&expr.token, &target, &index.to_string(), range.len())
+                })?)
+            }
+            (LitKind::List(list), &LitKind::Number(n)) => list
+                .get(n)
+                .cloned()
+                .ok_or_else(|| out_of_range(&expr.token, &target, &index.to_string(), list.len()))?,
+            (LitKind::String(s), &LitKind::Number(n)) => {
+                let len = s.chars().count();
+                let i = to_index(n, len)
+                    .ok_or_else(|| out_of_range(&expr.token, &target, &index.to_string(), len))?;
+                let c = s.chars().nth(i).expect("checked against the length");
+                LitKind::String(c.to_string())
+            }
+            _ => {
+                return Err(LoxError::RuntimeError(
+                    GenericError::new(&expr.token, ErrorCode::InvalidOperand, "invalid index")
+                        .with_note(format!(
+                            "lists, strings and ranges are indexed by numbers and maps by keys; this is a {} indexed by a {}",
+                            target.type_name(),
+                            index.type_name()
+                        )),
+                ))
+            }
+        };
+        Ok(value)
+    }
+
+    /// Leaving out a bound slices from the start or to the end. The bounds
+    /// have to be in order and within the list or string, they are never
+    /// clamped.
+    fn visit_slice(
+        &mut self,
+        expr: &Expr,
+        target: &Expr,
+        start: Option<&Expr>,
+        end: Option<&Expr>,
+    ) -> Self::Output {
+        let target = self.visit_expr(target)?;
+        let mut bound = |e: Option<&Expr>| e.map(|e| self.visit_expr(e)).transpose();
+        let (start, end) = (bound(start)?, bound(end)?);
+        let len = match &target {
+            LitKind::List(list) => list.len(),
+            LitKind::String(s) => s.chars().count(),
+            _ => {
+                return Err(LoxError::RuntimeError(
+                    GenericError::new(&expr.token, ErrorCode::InvalidOperand, "invalid slice")
+                        .with_note(format!(
+                            "only lists and strings can be sliced, this is a {}",
+                            target.type_name()
+                        )),
+                ))
+            }
+        };
+        let shown = |b: &Option<LitKind>| b.as_ref().map_or(String::new(), |b| b.to_string());
+        let err = || {
+            out_of_range(
+                &expr.token,
+                &target,
+                &format!("{}:{}", shown(&start), shown(&end)),
+                len,
+            )
+        };
+        let index = |b: &Option<LitKind>, default| match b {
+            None => Some(default),
+            Some(LitKind::Number(n)) => to_index(*n, len + 1),
+            Some(_) => None,
+        };
+        let (Some(from), Some(to)) = (index(&start, 0), index(&end, len)) else {
+            return Err(err());
+        };
+        if from > to {
+            return Err(err());
+        }
+        Ok(match &target {
+            LitKind::List(list) => LitKind::List(list.slice(from, to).ok_or_else(err)?),
+            LitKind::String(s) => LitKind::String(s.chars().skip(from).take(to - from).collect()),
+            _ => unreachable!("only lists and strings get this far"),
+        })
+    }
+
+    fn visit_variable(&mut self, expr: &Expr, name: &str) -> Self::Output {
+        let mut scopes = self.scopes.iter().rev().chain([&self.globals]);
+        scopes.find_map(|s| s.get(name)).cloned().ok_or_else(|| {
+            LoxError::new_runtime(
+                &expr.token,
+                ErrorCode::UndefinedVariable,
+                &format!("undefined variable '{name}'"),
+            )
+        })
+    }
+
+    fn visit_call(&mut self, expr: &Expr, callee: &Expr, args: &[Expr]) -> Self::Output {
+        let callee = self.visit_expr(callee)?;
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            match (&arg.kind, self.visit_expr(arg)?) {
+                (ExprKind::Spread(_), LitKind::List(list)) => values.extend(list.iter().cloned()),
+                (_, value) => values.push(value),
+            }
+        }
+        let args = values;
+        let LitKind::Native(function) = callee else {
+            return Err(LoxError::RuntimeError(
+                GenericError::new(&expr.token, ErrorCode::NotCallable, "not a function").with_note(
+                    format!(
+                        "only functions can be called, this is a {}",
+                        callee.type_name()
+                    ),
+                ),
+            ));
+        };
+        if args.len() < function.arity || !function.variadic && args.len() != function.arity {
+            return Err(LoxError::new_runtime(
+                &expr.token,
+                ErrorCode::WrongArity,
+                &format!(
+                    "{} expects {}{} arguments but got {}",
+                    function.name,
+                    if function.variadic { "at least " } else { "" },
+                    function.arity,
+                    args.len()
+                ),
+            ));
+        }
+        function.call(self, &args).map_err(|e| match e {
+            // Raised by the native itself, which doesn't know where it was called
+            LoxError::RuntimeError(e) if e.span == Span::default() => {
+                LoxError::RuntimeError(e.relocate(&expr.token))
+            }
+            e => e,
+        })
+    }
+
+    fn visit_match(
+        &mut self,
+        _expr: &Expr,
+        subject: &Expr,
+        arms: &[(Pattern, Expr)],
+        otherwise: &Expr,
+    ) -> Self::Output {
+        let value = self.visit_expr(subject)?;
+        for (pattern, body) in arms {
+            let mut bindings = HashMap::new();
+            if pattern.bind(&value, &mut bindings) {
+                self.scopes.push(bindings);
+                let result = self.visit_expr(body);
+                self.scopes.pop();
+                return result;
+            }
+        }
+        self.visit_expr(otherwise)
+    }
+
+    /// The items to pass, as a list; `visit_call` passes them one by one.
+    fn visit_spread(&mut self, expr: &Expr, inner: &Expr) -> Self::Output {
+        match self.visit_expr(inner)? {
+            list @ LitKind::List(_) => Ok(list),
+            LitKind::Range(range) => Ok(LitKind::List(LoxList::new(
+                range.iter().map(LitKind::Number).collect(),
+            ))),
+            other => Err(LoxError::RuntimeError(
+                GenericError::new(&expr.token, ErrorCode::InvalidOperand, "cannot spread")
+                    .with_note(format!(
+                        "only lists and ranges can be spread, this is a {}",
+                        other.type_name()
+                    )),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse_tokens, scanner::scan_tokens};
+
+    fn eval(source: &str) -> LitKind {
+        let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+        Interpreter::new().interpret(&expr).unwrap()
+    }
+
+    /// Checks that `source` fails at runtime with `code`, and returns the
+    /// error for any further checks.
+    fn assert_runtime_error(source: &str, code: ErrorCode) -> GenericError {
+        let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+        match Interpreter::new().interpret(&expr) {
+            Err(LoxError::RuntimeError(e)) => {
+                assert_eq!(e.code, code, "{source}");
+                e
+            }
+            other => panic!("{source}: expected a runtime error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_truthiness() {
+        for (value, truthy) in [
+            ("nil", false),
+            ("false", false),
+            ("true", true),
+            ("0", true),
+            ("-1", true),
+            ("\"\"", true),
+            ("\"false\"", true),
+        ] {
+            assert_eq!(
+                eval(&format!("!{value}")),
+                LitKind::Boolean(!truthy),
+                "!{value}"
+            );
+            assert_eq!(
+                eval(&format!("!!{value}")),
+                LitKind::Boolean(truthy),
+                "!!{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_equality() {
+        let values = ["nil", "true", "false", "0", "1", "\"\"", "\"1\"", "\"a\""];
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                let want = i == j;
+                assert_eq!(
+                    eval(&format!("{a} == {b}")),
+                    LitKind::Boolean(want),
+                    "{a} == {b}"
+                );
+                assert_eq!(
+                    eval(&format!("{a} != {b}")),
+                    LitKind::Boolean(!want),
+                    "{a} != {b}"
+                );
+            }
+        }
+        assert_eq!(eval("1 + 1 == 2"), LitKind::Boolean(true));
+        assert_eq!(eval("\"a\" + \"b\" == \"ab\""), LitKind::Boolean(true));
+        let mut ieee = Interpreter::new();
+        ieee.ieee_division = true;
+        let nan = parse_tokens(&scan_tokens("0 / 0 == 0 / 0").unwrap()).unwrap();
+        assert_eq!(ieee.interpret(&nan).unwrap(), LitKind::Boolean(false));
+        // Two nils can be compared for equality, and nothing else
+        for op in ["+", "-", "*", "/", "%", "<", "<=", ">", ">="] {
+            let source = format!("nil {op} nil");
+            assert_runtime_error(&source, ErrorCode::IncompatibleOperands);
+        }
+    }
+
+    #[test]
+    fn test_string_number_concatenation() {
+        assert_eq!(eval("\"scone\" + 4"), LitKind::String("scone4".to_string()));
+        assert_eq!(eval("1.5 + \"x\""), LitKind::String("1.5x".to_string()));
+        assert_eq!(eval("\"\" + -2 + 1"), LitKind::String("-21".to_string()));
+        assert_eq!(eval("1 + 2 + \"\""), LitKind::String("3".to_string()));
+        assert_runtime_error("\"a\" - 1", ErrorCode::IncompatibleOperands);
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let e = assert_runtime_error("1 + 2 / (1 - 1)", ErrorCode::DivisionByZero);
+        assert_eq!(e.span.start, 6);
+
+        let expr = parse_tokens(&scan_tokens("1 + 2 / (1 - 1)").unwrap()).unwrap();
+        let mut ieee = Interpreter::new();
+        ieee.ieee_division = true;
+        assert_eq!(
+            ieee.interpret(&expr).unwrap(),
+            LitKind::Number(f32::INFINITY)
+        );
+        assert_eq!(eval("0 / 2"), LitKind::Number(0.));
+    }
+
+    #[test]
+    fn test_modulo() {
+        assert_eq!(eval("7 % 3"), LitKind::Number(1.));
+        assert_eq!(eval("-7 % 3"), LitKind::Number(-1.));
+        assert_eq!(eval("7.5 % 2"), LitKind::Number(1.5));
+        assert_eq!(eval("1 + 10 % 4 * 2"), LitKind::Number(5.));
+        let expr = parse_tokens(&scan_tokens("1 % 0").unwrap()).unwrap();
+        assert!(Interpreter::new().interpret(&expr).is_err());
+    }
+
+    #[test]
+    fn test_power() {
+        assert_eq!(eval("2 ** 10"), LitKind::Number(1024.));
+        assert_eq!(eval("-2 ** 2"), LitKind::Number(-4.));
+        assert_eq!(eval("(-2) ** 2"), LitKind::Number(4.));
+        assert_eq!(eval("2 ** 3 ** 2"), LitKind::Number(512.));
+        assert_eq!(eval("2 ** -1"), LitKind::Number(0.5));
+        assert_eq!(eval("3 * 2 ** 2"), LitKind::Number(12.));
+    }
+
+    #[test]
+    fn test_bitwise() {
+        assert_eq!(eval("6 & 3"), LitKind::Number(2.));
+        assert_eq!(eval("6 | 3"), LitKind::Number(7.));
+        assert_eq!(eval("6 ^ 3"), LitKind::Number(5.));
+        assert_eq!(eval("~5"), LitKind::Number(-6.));
+        assert_eq!(eval("1 << 4"), LitKind::Number(16.));
+        assert_eq!(eval("-8 >> 1"), LitKind::Number(-4.));
+        // Looser than arithmetic, tighter than comparison
+        assert_eq!(eval("1 | 2 & 3 << 1 + 1"), LitKind::Number(1.));
+        assert_eq!(eval("1 | 2 == 3"), LitKind::Boolean(true));
+
+        for source in ["1.5 & 1", "1 | 4294967296", "~0.5", "1 << 32", "1 >> -1"] {
+            assert_runtime_error(source, ErrorCode::NotAnInteger);
+        }
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(eval("[1, \"a\", nil][1]"), LitKind::String("a".to_string()));
+        assert_eq!(eval("[[1, 2], [3]][0][1] * 2"), LitKind::Number(4.));
+        assert_eq!(eval("[1, [2]] == [1, [2]]"), LitKind::Boolean(true));
+        assert_eq!(eval("[1, 2] == [2, 1]"), LitKind::Boolean(false));
+        assert_eq!(eval("[] == []"), LitKind::Boolean(true));
+        assert_eq!(eval("[1, \"1\"]").to_string(), "[1, \"1\"]");
+
+        for (source, code) in [
+            ("[1, 2][2]", ErrorCode::IndexOutOfRange),
+            ("[1, 2][-1]", ErrorCode::IndexOutOfRange),
+            ("[1, 2][0.5]", ErrorCode::IndexOutOfRange),
+            ("[1][\"0\"]", ErrorCode::InvalidOperand),
+            ("1[0]", ErrorCode::InvalidOperand),
+        ] {
+            assert_runtime_error(source, code);
+        }
+    }
+
+    #[test]
+    fn test_maps() {
+        let map = "{\"b\": 1, 2: [3], \"a\": nil}";
+        assert_eq!(eval(&format!("{map}[\"b\"]")), LitKind::Number(1.));
+        assert_eq!(eval(&format!("{map}[1 + 1][0]")), LitKind::Number(3.));
+        assert_eq!(eval(&format!("{map}[\"a\"]")), LitKind::Nil);
+        assert_eq!(
+            eval("{0: \"zero\"}[-0]"),
+            LitKind::String("zero".to_string())
+        );
+        // Entries keep insertion order, and a repeated key keeps its place
+        assert_eq!(eval(map).to_string(), "{\"b\": 1, 2: [3], \"a\": nil}");
+        assert_eq!(eval("{1: 1, 2: 2, 1: 3}").to_string(), "{1: 3, 2: 2}");
+        assert_eq!(eval("{} == {}"), LitKind::Boolean(true));
+        assert_eq!(eval("{1: 2, 3: 4} == {3: 4, 1: 2}"), LitKind::Boolean(true));
+        assert_eq!(eval("{1: 2} == {1: \"2\"}"), LitKind::Boolean(false));
+
+        for (source, code) in [
+            ("{1: 2}[\"1\"]", ErrorCode::KeyNotFound),
+            ("{nil: 1}", ErrorCode::InvalidOperand),
+            ("{[1]: 1}", ErrorCode::InvalidOperand),
+        ] {
+            assert_runtime_error(source, code);
+        }
+    }
+
+    #[test]
+    fn test_strings() {
+        let s = "\"héllo\"";
+        assert_eq!(eval(&format!("{s}[1]")), LitKind::String("é".to_string()));
+        assert_eq!(
+            eval(&format!("{s}[1:4]")),
+            LitKind::String("éll".to_string())
+        );
+        assert_eq!(
+            eval(&format!("{s}[:2] + {s}[4:]")),
+            LitKind::String("héo".to_string())
+        );
+        assert_eq!(eval(&format!("{s}[5:]")), LitKind::String("".to_string()));
+        assert_eq!(
+            eval(&format!("{s}[:]")),
+            LitKind::String("héllo".to_string())
+        );
+        assert_eq!(eval("[1, 2, 3][1:]"), eval("[2, 3]"));
+
+        for source in [
+            "\"ab\"[2]",
+            "\"ab\"[1:3]",
+            "\"ab\"[2:1]",
+            "\"ab\"[0.5:]",
+            "[1][:2]",
+        ] {
+            assert_runtime_error(source, ErrorCode::IndexOutOfRange);
+        }
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(eval("(0..3)[2]"), LitKind::Number(2.));
+        assert_eq!(eval("(1..=3)[2]"), LitKind::Number(3.));
+        assert_eq!(eval("(0..1000000000)[999999]"), LitKind::Number(999999.));
+        assert_eq!(eval("0..3 == 0..=2"), LitKind::Boolean(true));
+        assert_eq!(eval("3..0 == 5..5"), LitKind::Boolean(true));
+        assert_eq!(eval("0..3 == 1..4"), LitKind::Boolean(false));
+        assert_eq!(eval("1 + 1..2 * 3").to_string(), "2..6");
+        assert_eq!(eval("0..=1.5").to_string(), "0..=1.5");
+
+        for source in ["(0..3)[3]", "(0..=3)[-1]", "(5..0)[0]"] {
+            assert_runtime_error(source, ErrorCode::IndexOutOfRange);
+        }
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(eval("clock == clock"), LitKind::Boolean(true));
+        assert_eq!(eval("clock").to_string(), "<native fn clock>");
+        assert!(matches!(eval("clock() - 1"), LitKind::Number(_)));
+
+        // Reported at the call, since the native has no location
+        let e = assert_runtime_error("1 + pow(2, nil)", ErrorCode::InvalidOperand);
+        assert_eq!(e.span.start, 7);
+
+        assert_eq!(eval("pow(...[2, 3])"), LitKind::Number(8.));
+        assert_eq!(eval("max(...1..=1, ...[], 2)"), LitKind::Number(2.));
+
+        for (source, code) in [
+            ("clock(1)", ErrorCode::WrongArity),
+            ("pow(...[2])", ErrorCode::WrongArity),
+            ("pow(...2, 3)", ErrorCode::InvalidOperand),
+            ("nope()", ErrorCode::UndefinedVariable),
+            ("\"clock\"()", ErrorCode::NotCallable),
+        ] {
+            assert_runtime_error(source, code);
+        }
+    }
+
+    #[test]
+    fn test_coalesce() {
+        assert_eq!(eval("nil ?? 1"), LitKind::Number(1.));
+        assert_eq!(eval("false ?? 1"), LitKind::Boolean(false));
+        assert_eq!(eval("nil ?? nil ?? \"c\""), LitKind::String("c".into()));
+        // Binds looser than ==, and only evaluates the right side for nil
+        assert_eq!(eval("nil ?? 1 == 2"), LitKind::Boolean(false));
+        assert_eq!(eval("0 ?? exit(1)"), LitKind::Number(0.));
+    }
+
+    #[test]
+    fn test_match() {
+        let describe = |value: &str| {
+            eval(&format!(
+                "match ({value}) {{ 1 -> \"one\"; -1 -> \"minus one\"; [] -> \"empty\"; \
+                 [x] -> format(\"just {{}}\", x); [a, _, a2] -> a + a2; \
+                 {{\"name\": n}} -> n; else -> \"something\" }}"
+            ))
+            .to_string()
+        };
+        assert_eq!(describe("1"), "one");
+        assert_eq!(describe("0 - 1"), "minus one");
+        assert_eq!(describe("[]"), "empty");
+        assert_eq!(describe("[[2]]"), "just [2]");
+        assert_eq!(describe("[1, 2, 3]"), "4");
+        assert_eq!(describe("{\"age\": 3, \"name\": \"jo\"}"), "jo");
+        assert_eq!(describe("[1, 2]"), "something");
+        assert_eq!(describe("\"1\""), "something");
+
+        // Bindings shadow globals, and only inside their arm
+        assert_eq!(
+            eval("match (2) { PI -> PI; else -> 0 } + PI"),
+            LitKind::Number(2. + std::f32::consts::PI)
+        );
+        let expr =
+            parse_tokens(&scan_tokens("match (1) { x -> error(\"no\"); else -> 0 }").unwrap())
+                .unwrap();
+        let mut interpreter = Interpreter::new();
+        assert!(interpreter.interpret(&expr).is_err());
+        assert!(interpreter.scopes.is_empty());
+    }
+
+    #[test]
+    fn test_comparison() {
+        for (source, want) in [
+            ("1 < 2", true),
+            ("2 <= 2", true),
+            ("-1 > 0", false),
+            ("\"abc\" < \"abd\"", true),
+            ("\"ab\" < \"abc\"", true),
+            ("\"B\" < \"a\"", true),
+            ("\"b\" >= \"b\"", true),
+            ("\"é\" > \"z\"", true),
+            ("\"\" > \"a\"", false),
+        ] {
+            assert_eq!(eval(source), LitKind::Boolean(want), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_current_location() {
+        let mut interpreter = Interpreter::new();
+        for source in ["1 + 2", "1 + (2 + nil)"] {
+            let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+            let _ = interpreter.interpret(&expr);
+            // Cleared on the way out, also when evaluation failed
+            assert_eq!(current_location(), None, "{source}");
+        }
     }
 }
@@ -0,0 +1,90 @@
+use crate::ast::{walk_children, Expr, Visitor};
+
+/*
+* NOTE: These are checks on our own code, not on the script: a failure means
+* a pass built a tree the rest of the interpreter can't trust, so we panic
+* (and the panic hook asks for a bug report) instead of reporting a Lox
+* error. They only run in builds with debug assertions.
+*/
+
+/// Panics if `expr` breaks an invariant the passes rely on: every node's
+/// span covers its children, children are created before their parent,
+/// and the recorded depth is the real height of the tree.
+pub fn check_tree(expr: &Expr) {
+    TreeChecker.visit_expr(expr);
+}
+
+struct TreeChecker;
+
+/// Each visit returns the height of the tree it checked.
+impl Visitor for TreeChecker {
+    type Output = usize;
+
+    fn combine(&mut self, expr: &Expr, depths: Vec<usize>) -> usize {
+        for child in expr.children() {
+            assert!(
+                child.id < expr.id,
+                "node {} was created after its parent {}",
+                child.id,
+                expr.id
+            );
+            assert!(
+                expr.span.start <= child.span.start && child.span.end <= expr.span.end,
+                "span of node {} is not inside the span of its parent {}",
+                child.id,
+                expr.id
+            );
+        }
+        let depth = depths.into_iter().max().unwrap_or(0) + 1;
+        assert_eq!(expr.depth, depth, "node {} has a stale depth", expr.id);
+        depth
+    }
+
+    // Groupings are nodes of their own here
+    fn visit_grouping(&mut self, expr: &Expr, _inner: &Expr) -> usize {
+        walk_children(self, expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast::{ExprKind, Folder},
+        optimize::ConstantFolder,
+        parser::parse_tokens,
+        scanner::scan_tokens,
+    };
+
+    fn parse(source: &str) -> Expr {
+        parse_tokens(&scan_tokens(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_passes_keep_invariants() {
+        for source in [
+            "1 + 2 * -(3 - \"a\")",
+            "!(nil == (1 < 2))",
+            "1 / 0",
+            "[1, [2 + 3]][1][0]",
+            "{\"a\": [1], 2: {}}[\"a\"]",
+            "\"abc\"[1 + 0:][:1]",
+            "clock(1, [2], ...[3])",
+            "match (x) { [a, {\"k\": b}] -> a + b; else -> nil }",
+        ] {
+            let expr = parse(source);
+            check_tree(&expr);
+            check_tree(&ConstantFolder::default().fold_expr(expr));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "stale depth")]
+    fn test_catches_stale_depth() {
+        let mut expr = parse("-(1 + 2)");
+        if let ExprKind::Unary(inner, _) = &mut expr.kind {
+            inner.depth = 1;
+        }
+        check_tree(&expr);
+    }
+}
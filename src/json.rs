@@ -0,0 +1,302 @@
+use itertools::Itertools;
+use serde_json::{Number, Value};
+
+use crate::ast::{BinOp, Expr, LitKind, LoxList, LoxMap, Pattern, UnOp, Visitor};
+
+/// Serializes the syntax tree to JSON, one object per node, with lines
+/// 1-based as editors count them:
+/// `{"type": "Binary", "op": "+", "left": .., "right": .., "line": 1, "span": [0, 5]}`.
+pub struct AstJson;
+
+impl AstJson {
+    pub fn serialize(expr: &Expr) -> String {
+        AstJson.visit_expr(expr)
+    }
+}
+
+/// Quotes and escapes `s` as a JSON string.
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn value(lit: &LitKind) -> String {
+    match lit {
+        LitKind::String(s) => escape(s),
+        LitKind::Number(n) if !n.is_finite() => "null".to_string(),
+        LitKind::List(list) => format!("[{}]", list.iter().map(value).join(", ")),
+        LitKind::Range(range) => escape(&range.to_string()),
+        LitKind::Native(native) => escape(&native.to_string()),
+        // JSON keys are always strings
+        LitKind::Map(map) => format!(
+            "{{{}}}",
+            map.iter()
+                .map(|(k, v)| match k {
+                    LitKind::String(s) => format!("{}: {}", escape(s), value(v)),
+                    _ => format!("{}: {}", escape(&k.to_string()), value(v)),
+                })
+                .join(", ")
+        ),
+        _ => lit.to_string(),
+    }
+}
+
+/// A value as JSON data, for `jsonStringify`. Number keys become strings,
+/// as JSON keys always are. `Err` names what has no JSON equivalent.
+pub fn to_data(lit: &LitKind) -> Result<Value, String> {
+    Ok(match lit {
+        LitKind::Nil => Value::Null,
+        LitKind::Boolean(b) => Value::Bool(*b),
+        LitKind::String(s) => Value::String(s.clone()),
+        // Integers print without a fraction, and the rest the way Lox prints
+        // them, so 0.1 doesn't come out as 0.10000000149011612
+        LitKind::Number(n) if n.fract() == 0.0 && n.abs() < 2f32.powi(53) => Value::from(*n as i64),
+        LitKind::Number(n) => n
+            .to_string()
+            .parse()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+            .ok_or_else(|| format!("the number {n}"))?,
+        LitKind::List(list) => Value::Array(list.iter().map(to_data).try_collect()?),
+        LitKind::Map(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| {
+                    let key = match k {
+                        LitKind::String(s) => s.clone(),
+                        _ => k.to_string(),
+                    };
+                    to_data(v).map(|v| (key, v))
+                })
+                .try_collect()?,
+        ),
+        LitKind::Range(_) | LitKind::Native(_) => Err(format!("a {}", lit.type_name()))?,
+    })
+}
+
+/// JSON data as a value, for `jsonParse`. Objects keep their key order.
+pub fn from_data(data: Value) -> LitKind {
+    match data {
+        Value::Null => LitKind::Nil,
+        Value::Bool(b) => LitKind::Boolean(b),
+        Value::Number(n) => LitKind::Number(n.as_f64().unwrap_or(f64::NAN) as f32),
+        Value::String(s) => LitKind::String(s),
+        Value::Array(items) => {
+            LitKind::List(LoxList::new(items.into_iter().map(from_data).collect()))
+        }
+        Value::Object(entries) => {
+            let mut map = LoxMap::default();
+            for (key, value) in entries {
+                map.insert(LitKind::String(key), from_data(value));
+            }
+            LitKind::Map(map)
+        }
+    }
+}
+
+/// Patterns aren't nodes, so they have no id, line or span.
+fn pattern(p: &Pattern) -> String {
+    match p {
+        Pattern::Literal(lit) => format!("{{\"type\": \"Literal\", \"value\": {}}}", value(lit)),
+        Pattern::Binding(name) => format!("{{\"type\": \"Binding\", \"name\": {}}}", escape(name)),
+        Pattern::List(items) => format!(
+            "{{\"type\": \"List\", \"items\": [{}]}}",
+            items.iter().map(pattern).join(", ")
+        ),
+        Pattern::Map(entries) => format!(
+            "{{\"type\": \"Map\", \"entries\": [{}]}}",
+            entries
+                .iter()
+                .map(|(k, p)| format!("{{\"key\": {}, \"value\": {}}}", value(k), pattern(p)))
+                .join(", ")
+        ),
+    }
+}
+
+fn node(expr: &Expr, kind: &str, fields: &[(&str, String)]) -> String {
+    let mut out = format!("{{\"id\": {}, \"type\": {}", expr.id, escape(kind));
+    for (name, value) in fields {
+        out += &format!(", {}: {value}", escape(name));
+    }
+    let span = &expr.span;
+    out += &format!(
+        ", \"line\": {}, \"span\": [{}, {}]}}",
+        expr.token.line + 1,
+        span.start,
+        span.end
+    );
+    out
+}
+
+impl Visitor for AstJson {
+    type Output = String;
+
+    // Every kind of node has its own visit
+    fn combine(&mut self, _expr: &Expr, _children: Vec<String>) -> String {
+        unreachable!()
+    }
+
+    fn visit_literal(&mut self, expr: &Expr, lit: &LitKind) -> String {
+        node(expr, "Literal", &[("value", value(lit))])
+    }
+
+    fn visit_unary(&mut self, expr: &Expr, inner: &Expr, op: &UnOp) -> String {
+        let operand = self.visit_expr(inner);
+        node(
+            expr,
+            "Unary",
+            &[("op", escape(&op.to_string())), ("operand", operand)],
+        )
+    }
+
+    fn visit_binary(&mut self, expr: &Expr, left: &Expr, right: &Expr, op: &BinOp) -> String {
+        let left = self.visit_expr(left);
+        let right = self.visit_expr(right);
+        node(
+            expr,
+            "Binary",
+            &[
+                ("op", escape(&op.to_string())),
+                ("left", left),
+                ("right", right),
+            ],
+        )
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr, inner: &Expr) -> String {
+        let inner = self.visit_expr(inner);
+        node(expr, "Grouping", &[("expression", inner)])
+    }
+
+    fn visit_list(&mut self, expr: &Expr, items: &[Expr]) -> String {
+        let items = items.iter().map(|e| self.visit_expr(e)).join(", ");
+        node(expr, "List", &[("items", format!("[{items}]"))])
+    }
+
+    fn visit_map(&mut self, expr: &Expr, entries: &[(Expr, Expr)]) -> String {
+        let entries = entries
+            .iter()
+            .map(|(k, v)| {
+                let (k, v) = (self.visit_expr(k), self.visit_expr(v));
+                format!("{{\"key\": {k}, \"value\": {v}}}")
+            })
+            .join(", ");
+        node(expr, "Map", &[("entries", format!("[{entries}]"))])
+    }
+
+    fn visit_index(&mut self, expr: &Expr, list: &Expr, index: &Expr) -> String {
+        let list = self.visit_expr(list);
+        let index = self.visit_expr(index);
+        node(expr, "Index", &[("list", list), ("index", index)])
+    }
+
+    fn visit_slice(
+        &mut self,
+        expr: &Expr,
+        target: &Expr,
+        start: Option<&Expr>,
+        end: Option<&Expr>,
+    ) -> String {
+        let target = self.visit_expr(target);
+        let mut bound = |e: Option<&Expr>| e.map_or("null".to_string(), |e| self.visit_expr(e));
+        let (start, end) = (bound(start), bound(end));
+        node(
+            expr,
+            "Slice",
+            &[("target", target), ("start", start), ("end", end)],
+        )
+    }
+
+    fn visit_variable(&mut self, expr: &Expr, name: &str) -> String {
+        node(expr, "Variable", &[("name", escape(name))])
+    }
+
+    fn visit_call(&mut self, expr: &Expr, callee: &Expr, args: &[Expr]) -> String {
+        let callee = self.visit_expr(callee);
+        let args = args.iter().map(|e| self.visit_expr(e)).join(", ");
+        node(
+            expr,
+            "Call",
+            &[("callee", callee), ("arguments", format!("[{args}]"))],
+        )
+    }
+
+    fn visit_spread(&mut self, expr: &Expr, inner: &Expr) -> String {
+        let operand = self.visit_expr(inner);
+        node(expr, "Spread", &[("operand", operand)])
+    }
+
+    fn visit_match(
+        &mut self,
+        expr: &Expr,
+        subject: &Expr,
+        arms: &[(Pattern, Expr)],
+        otherwise: &Expr,
+    ) -> String {
+        let subject = self.visit_expr(subject);
+        let arms = arms
+            .iter()
+            .map(|(p, body)| {
+                let body = self.visit_expr(body);
+                format!("{{\"pattern\": {}, \"body\": {body}}}", pattern(p))
+            })
+            .join(", ");
+        let otherwise = self.visit_expr(otherwise);
+        node(
+            expr,
+            "Match",
+            &[
+                ("subject", subject),
+                ("arms", format!("[{arms}]")),
+                ("else", otherwise),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::ExprKind, parser::parse_tokens, scanner::scan_tokens};
+
+    #[test]
+    fn test_serialize() {
+        let expr = parse_tokens(&scan_tokens("-1 + \"a\nb\"").unwrap()).unwrap();
+        let ExprKind::Binary(left, right, _) = &expr.kind else {
+            panic!("expected a binary expression");
+        };
+        let ExprKind::Unary(one, _) = &left.kind else {
+            panic!("expected a unary expression");
+        };
+        let want = format!(
+            concat!(
+                r#"{{"id": {}, "type": "Binary", "op": "+", "#,
+                r#""left": {{"id": {}, "type": "Unary", "op": "-", "#,
+                r#""operand": {{"id": {}, "type": "Literal", "value": 1, "line": 1, "span": [1, 2]}}, "#,
+                r#""line": 1, "span": [0, 2]}}, "#,
+                r#""right": {{"id": {}, "type": "Literal", "value": "a\nb", "line": 2, "span": [5, 10]}}, "#,
+                r#""line": 1, "span": [0, 10]}}"#
+            ),
+            expr.id, left.id, one.id, right.id
+        );
+        assert_eq!(AstJson::serialize(&expr), want);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("tab\there\n\u{1}"), r#""tab\there\n\u0001""#);
+    }
+}
@@ -0,0 +1,121 @@
+//! jilox, a Lox interpreter. Besides the `jilox` binary, it can be
+//! embedded as a scripting engine through [`Lox`].
+
+pub mod ast;
+pub mod diagnostics;
+pub mod dot;
+pub mod errors;
+pub mod interpreter;
+pub mod invariants;
+pub mod json;
+pub mod lint;
+pub mod natives;
+pub mod optimize;
+pub mod parser;
+pub mod scanner;
+
+pub use ast::{Expr, ExprKind, LitKind, Pattern, Value};
+pub use errors::{ErrorCode, GenericError, LoxError};
+pub use interpreter::Interpreter;
+pub use natives::{FromArgs, NativeFunction};
+pub use parser::parse_tokens;
+pub use scanner::{scan_tokens, Token, TokenType};
+
+/// An interpreter to run scripts in. Globals defined on it, natives
+/// included, stay defined from one `run` to the next.
+#[derive(Default)]
+pub struct Lox {
+    interpreter: Interpreter,
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scans, parses and evaluates `source`. A script that calls `exit()`
+    /// comes back as `LoxError::Exit` with its status; what to do with it
+    /// is up to the host.
+    pub fn run(&mut self, source: &str) -> Result<Value, LoxError> {
+        let expr = parse_tokens(&scan_tokens(source)?)?;
+        self.interpreter.interpret(&expr)
+    }
+
+    /// Lets scripts call `func` as `name`, see `Interpreter::register_fn`.
+    pub fn register_fn<A, R>(
+        &mut self,
+        name: &str,
+        func: impl Fn(A) -> Result<R, LoxError> + 'static,
+    ) where
+        A: FromArgs,
+        R: Into<Value>,
+    {
+        self.interpreter.register_fn(name, func);
+    }
+
+    /// The interpreter scripts run in, e.g. to define globals for them.
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let mut lox = Lox::new();
+        lox.interpreter().define("answer", Value::Number(42.));
+        assert_eq!(lox.run("answer + 1").unwrap(), Value::Number(43.));
+        assert_eq!(lox.run("answer").unwrap(), Value::Number(42.));
+        assert!(matches!(lox.run("(1"), Err(LoxError::ParseError(_))));
+        assert!(matches!(lox.run("exit(2)"), Err(LoxError::Exit(2))));
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut lox = Lox::new();
+        lox.register_fn("count", |(value,): (Value,)| match value {
+            Value::List(list) => Ok(Value::Number(list.len() as f32)),
+            _ => Err(natives::native_error(
+                ErrorCode::InvalidOperand,
+                "not a list",
+            )),
+        });
+        assert_eq!(lox.run("count([1, 2]) * 2").unwrap(), Value::Number(4.));
+        match lox.run("count(1)") {
+            Err(LoxError::RuntimeError(e)) => assert_eq!(e.span.start, 5),
+            other => panic!("expected a runtime error, got {other:?}"),
+        }
+        assert!(lox.run("count()").is_err());
+
+        lox.register_fn("shout", |(s,): (String,)| Ok(s.to_uppercase()));
+        assert_eq!(lox.run("shout(\"hi\")").unwrap(), Value::from("HI"));
+        assert!(lox.run("shout(1)").is_err());
+    }
+
+    #[test]
+    fn test_register_fn_conversions() {
+        let mut lox = Lox::new();
+        lox.register_fn("repeat", |(n, s): (f64, String)| Ok(s.repeat(n as usize)));
+        assert_eq!(lox.run("repeat(3, \"ab\")").unwrap(), Value::from("ababab"));
+        // A conversion that fails is an error at the call, like a native's
+        let source = "1 + repeat(\"ab\", 3)";
+        match lox.run(source) {
+            Err(LoxError::RuntimeError(e)) => {
+                assert_eq!(e.code, ErrorCode::InvalidOperand);
+                assert_eq!(e.span.start, 10);
+                assert!(e.render("error", source).ends_with("= note: in argument 1"));
+            }
+            other => panic!("expected a runtime error, got {other:?}"),
+        }
+        match lox.run("repeat(3)") {
+            Err(LoxError::RuntimeError(e)) => assert_eq!(e.code, ErrorCode::WrongArity),
+            other => panic!("expected a runtime error, got {other:?}"),
+        }
+
+        lox.register_fn("answer", |()| Ok(42.));
+        assert_eq!(lox.run("answer()").unwrap(), Value::Number(42.));
+    }
+}
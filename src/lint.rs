@@ -0,0 +1,279 @@
+use crate::{
+    ast::{BinOp, Expr, LitKind, UnOp, Visitor},
+    errors::{ErrorCode, GenericError},
+};
+
+/*
+* NOTE: This is a small abstract interpreter: instead of values, every
+* expression evaluates to what we can know about its value without running
+* the program. Anything we can't reason about becomes `Unknown`, so the
+* lints only fire on facts that hold for every execution.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fact {
+    Nil,
+    Boolean(Option<bool>),
+    /// The value lies within `lo..=hi`.
+    Number {
+        lo: f32,
+        hi: f32,
+    },
+    String,
+    Unknown,
+}
+
+impl Fact {
+    fn exact(n: f32) -> Self {
+        Self::Number { lo: n, hi: n }
+    }
+
+    fn range(a: f32, b: f32, c: f32, d: f32) -> Self {
+        Self::Number {
+            lo: a.min(b).min(c).min(d),
+            hi: a.max(b).max(c).max(d),
+        }
+    }
+
+    /// `Some` if the value is definitely truthy or definitely falsey.
+    fn truthiness(&self) -> Option<bool> {
+        match self {
+            Self::Nil => Some(false),
+            Self::Boolean(b) => *b,
+            Self::Number { .. } | Self::String => Some(true),
+            Self::Unknown => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct AbstractEvaluator {
+    pub warnings: Vec<GenericError>,
+}
+
+/// Runs the abstract evaluator over `expr` and returns everything it flagged.
+pub fn lint(expr: &Expr) -> Vec<GenericError> {
+    let mut evaluator = AbstractEvaluator::default();
+    evaluator.visit_expr(expr);
+    evaluator.warnings
+}
+
+impl AbstractEvaluator {
+    fn warn(&mut self, expr: &Expr, code: ErrorCode, message: &str) {
+        self.warnings
+            .push(GenericError::new(&expr.token, code, message));
+    }
+
+    fn eval_binary(&mut self, expr: &Expr, op: &BinOp, left: Fact, right: Fact) -> Fact {
+        use Fact::Number as N;
+        match (op, &left, &right) {
+            (BinOp::QuestionQuestion, Fact::Nil, _) => right,
+            (BinOp::QuestionQuestion, Fact::Unknown, _) => Fact::Unknown,
+            (BinOp::QuestionQuestion, _, _) => left,
+            (BinOp::EqualEqual | BinOp::BangEqual, _, _) => {
+                let equal = self.eval_equal(expr, &left, &right);
+                match op {
+                    BinOp::EqualEqual => Fact::Boolean(equal),
+                    _ => Fact::Boolean(equal.map(|b| !b)),
+                }
+            }
+            (
+                BinOp::Greater | BinOp::GreaterEqual | BinOp::Less | BinOp::LessEqual,
+                Fact::String,
+                N { .. },
+            )
+            | (
+                BinOp::Greater | BinOp::GreaterEqual | BinOp::Less | BinOp::LessEqual,
+                N { .. },
+                Fact::String,
+            ) => {
+                self.warn(
+                    expr,
+                    ErrorCode::StringComparedWithNumber,
+                    "string compared with number",
+                );
+                Fact::Unknown
+            }
+            (BinOp::Slash | BinOp::Percent, _, N { lo: 0.0, hi: 0.0 }) => {
+                self.warn(
+                    expr,
+                    ErrorCode::DivisionByConstantZero,
+                    "division by constant zero",
+                );
+                Fact::Unknown
+            }
+            (_, &N { lo: a, hi: b }, &N { lo: c, hi: d }) => match op {
+                BinOp::Plus => N {
+                    lo: a + c,
+                    hi: b + d,
+                },
+                BinOp::Minus => N {
+                    lo: a - d,
+                    hi: b - c,
+                },
+                BinOp::Star => Fact::range(a * c, a * d, b * c, b * d),
+                // Only safe to bound if the divisor can't cross zero.
+                BinOp::Slash if c > 0.0 || d < 0.0 => Fact::range(a / c, a / d, b / c, b / d),
+                BinOp::Less => compare(b < c, a >= d),
+                BinOp::LessEqual => compare(b <= c, a > d),
+                BinOp::Greater => compare(a > d, b <= c),
+                BinOp::GreaterEqual => compare(a >= d, b < c),
+                _ => Fact::Unknown,
+            },
+            (BinOp::Plus, Fact::String, Fact::String | N { .. })
+            | (BinOp::Plus, N { .. }, Fact::String) => Fact::String,
+            _ => Fact::Unknown,
+        }
+    }
+
+    /// Numbers are 32-bit floats for now, so integer literals past 2^24 are
+    /// silently rounded. They will be exact once numbers are 64-bit, which
+    /// changes what the script computes.
+    fn check_precision(&mut self, expr: &Expr, n: f32) {
+        let Ok(exact) = expr.token.lexeme.parse::<f64>() else {
+            return;
+        };
+        if exact.fract() == 0.0 && exact != f64::from(n) {
+            self.warnings.push(
+                GenericError::new(
+                    &expr.token,
+                    ErrorCode::ImpreciseNumber,
+                    &format!("{} is rounded to {n}", expr.token.lexeme),
+                )
+                .with_note("numbers will be 64-bit in a future version, where it stays exact"),
+            );
+        }
+    }
+
+    /// `Some` if the operands are definitely equal or definitely different.
+    fn eval_equal(&mut self, expr: &Expr, left: &Fact, right: &Fact) -> Option<bool> {
+        match (left, right) {
+            (Fact::Unknown, _) | (_, Fact::Unknown) => None,
+            (Fact::Nil, Fact::Nil) => Some(true),
+            (Fact::Boolean(Some(a)), Fact::Boolean(Some(b))) => Some(a == b),
+            (&Fact::Number { lo: a, hi: b }, &Fact::Number { lo: c, hi: d }) => {
+                if a == b && c == d {
+                    Some(a == c)
+                } else if b < c || d < a {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            (Fact::String, Fact::Number { .. }) | (Fact::Number { .. }, Fact::String) => {
+                self.warn(
+                    expr,
+                    ErrorCode::StringComparedWithNumber,
+                    "string compared with number",
+                );
+                Some(false)
+            }
+            (Fact::String, Fact::String) | (Fact::Boolean(_), Fact::Boolean(_)) => None,
+            _ => Some(false),
+        }
+    }
+}
+
+impl Visitor for AbstractEvaluator {
+    type Output = Fact;
+
+    // Nothing is known about what the other nodes evaluate to
+    fn combine(&mut self, _expr: &Expr, _children: Vec<Fact>) -> Fact {
+        Fact::Unknown
+    }
+
+    fn visit_literal(&mut self, expr: &Expr, lit: &LitKind) -> Fact {
+        match lit {
+            LitKind::Number(n) => {
+                self.check_precision(expr, *n);
+                Fact::exact(*n)
+            }
+            LitKind::String(_) => Fact::String,
+            LitKind::Boolean(b) => Fact::Boolean(Some(*b)),
+            LitKind::Nil => Fact::Nil,
+            LitKind::List(_) | LitKind::Map(_) | LitKind::Range(_) | LitKind::Native(_) => {
+                Fact::Unknown
+            }
+        }
+    }
+
+    fn visit_unary(&mut self, _expr: &Expr, inner: &Expr, op: &UnOp) -> Fact {
+        match (op, self.visit_expr(inner)) {
+            (UnOp::Minus, Fact::Number { lo, hi }) => Fact::Number { lo: -hi, hi: -lo },
+            (UnOp::Bang, fact) => Fact::Boolean(fact.truthiness().map(|b| !b)),
+            _ => Fact::Unknown,
+        }
+    }
+
+    fn visit_binary(&mut self, expr: &Expr, left: &Expr, right: &Expr, op: &BinOp) -> Fact {
+        let left = self.visit_expr(left);
+        let right = self.visit_expr(right);
+        self.eval_binary(expr, op, left, right)
+    }
+
+    fn visit_slice(
+        &mut self,
+        _expr: &Expr,
+        target: &Expr,
+        start: Option<&Expr>,
+        end: Option<&Expr>,
+    ) -> Fact {
+        let target = self.visit_expr(target);
+        for bound in [start, end].into_iter().flatten() {
+            self.visit_expr(bound);
+        }
+        match target {
+            Fact::String => Fact::String,
+            _ => Fact::Unknown,
+        }
+    }
+}
+
+fn compare(always: bool, never: bool) -> Fact {
+    Fact::Boolean(match (always, never) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse_tokens, scanner::scan_tokens};
+
+    fn messages(source: &str) -> Vec<String> {
+        let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+        lint(&expr).iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        assert_eq!(
+            messages("1 / (2 - 2)"),
+            vec!["line 0, \"/\": [W0001] division by constant zero"]
+        );
+        assert!(messages("1 / (2 - 1)").is_empty());
+    }
+
+    #[test]
+    fn test_string_compared_with_number() {
+        assert_eq!(messages("\"1\" == 1").len(), 1);
+        assert_eq!(messages("-1 != \"a\" + \"b\"").len(), 1);
+        assert!(messages("\"1\" == \"1\"").is_empty());
+    }
+
+    #[test]
+    fn test_facts() {
+        let fact = |source: &str| {
+            let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+            AbstractEvaluator::default().visit_expr(&expr)
+        };
+        assert_eq!(fact("-(2 * 3)"), Fact::exact(-6.));
+        assert_eq!(fact("!nil"), Fact::Boolean(Some(true)));
+        assert_eq!(fact("nil == false"), Fact::Boolean(Some(false)));
+        assert_eq!(fact("\"a\" == \"b\""), Fact::Boolean(None));
+        assert_eq!(fact("nil ?? 1 + 1"), Fact::exact(2.));
+        assert_eq!(fact("\"a\" ?? nil"), Fact::String);
+    }
+}
@@ -1,30 +1,387 @@
-use anyhow::Result;
-use std::env;
+use anyhow::{anyhow, Result};
+use std::{
+    cell::RefCell,
+    env, fs,
+    io::{self, Write},
+    panic,
+    path::PathBuf,
+    process,
+};
 
-mod ast;
-mod errors;
-mod interpreter;
-mod parser;
-mod scanner;
+use jilox::{
+    ast::{self, Folder},
+    diagnostics::{Diagnostics, WarningOptions},
+    dot,
+    errors::{ErrorCode, GenericError, LoxError},
+    interpreter::{self, Interpreter},
+    invariants, json, lint, optimize, parser, scanner,
+};
+
+mod selftest;
+
+const USAGE: &str =
+    "Usage: jilox [--prelude file | --no-prelude] [--tokens | --ast | --ast-dot | --dump-ast=json]
+             [--optimize] [--ieee-division] [--allow-fs] [--diagnostics=json] [-W[no-]lint | -W[no-]error] [--deny-warnings] [script [args...]]
+       jilox selftest";
+
+/// What to do with each piece of source we are handed.
+#[derive(Clone, Copy, PartialEq, Default)]
+enum Mode {
+    #[default]
+    Run,
+    /// Only scan, and print the token stream.
+    Tokens,
+    /// Only parse, and print the syntax tree.
+    Ast,
+    /// Only parse, and print the syntax tree as JSON.
+    AstJson,
+    /// Only parse, and print the syntax tree as a Graphviz graph.
+    AstDot,
+}
+
+/// How errors and warnings are shown.
+#[derive(Clone, Copy, PartialEq, Default)]
+enum DiagnosticFormat {
+    /// Rendered against the source, for people.
+    #[default]
+    Human,
+    /// One JSON object per line, for editors and CI.
+    Json,
+}
+
+/// Settings that apply to every piece of source we are handed.
+#[derive(Default)]
+struct Config {
+    mode: Mode,
+    /// Fold constant subexpressions before running (or dumping) the tree.
+    optimize: bool,
+    /// Dividing by zero gives inf or NaN instead of a runtime error.
+    ieee_division: bool,
+    /// Scripts may look at the file system.
+    allow_fs: bool,
+    diagnostics: DiagnosticFormat,
+    warnings: WarningOptions,
+}
 
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let mut prelude = default_prelude();
+    let mut script = None;
+    let mut script_args = Vec::new();
+    let mut config = Config::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--prelude" => {
+                let path = args.next().ok_or(anyhow!("--prelude expects a file"))?;
+                prelude = Some(PathBuf::from(path));
+            }
+            "--no-prelude" => prelude = None,
+            "--tokens" => config.mode = Mode::Tokens,
+            "--ast" => config.mode = Mode::Ast,
+            "--dump-ast=json" => config.mode = Mode::AstJson,
+            "--ast-dot" => config.mode = Mode::AstDot,
+            "--optimize" => config.optimize = true,
+            "--ieee-division" => config.ieee_division = true,
+            "--allow-fs" => config.allow_fs = true,
+            "--diagnostics=json" => config.diagnostics = DiagnosticFormat::Json,
+            _ if arg.starts_with("-W") => config.warnings.set(&arg[2..])?,
+            "--deny-warnings" => config.warnings.set("error")?,
+            "selftest" => {
+                let summary = selftest::run_selftest();
+                if summary.failed > 0 {
+                    return Err(anyhow!("{} self-test(s) failed", summary.failed));
+                }
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            // Everything after the script is for the script
+            _ => {
+                script = Some(arg);
+                script_args = args.by_ref().collect();
+            }
+        }
+    }
+
+    install_panic_hook(config.diagnostics);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.ieee_division = config.ieee_division;
+    interpreter.allow_fs = config.allow_fs;
+    interpreter.args = script_args;
+    if let (Some(path), Mode::Run) = (prelude, config.mode) {
+        run_prelude(&mut interpreter, &config, &path.to_string_lossy())?;
+    }
+
+    match script {
+        Some(file_name) => run_file(&mut interpreter, &config, &file_name),
+        None => run_prompt(&mut interpreter, &config),
+    }
+}
+
+thread_local! {
+    /// The file name and source being handled, for the panic hook.
+    static SOURCE: RefCell<(String, String)> = RefCell::default();
+}
+
+/// A panic is always a bug in jilox, never in the script, so instead of a
+/// bare Rust panic message we say so and ask for a report, pointing at the
+/// node the interpreter was evaluating. The default hook still runs when a
+/// backtrace was asked for.
+fn install_panic_hook(format: DiagnosticFormat) {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = info.payload_as_str().unwrap_or("unknown panic");
+        let location = info.location().map(|l| l.to_string()).unwrap_or_default();
+        let report =
+            "this is a bug in jilox, please report it along with the script that triggered it";
+        match (format, interpreter::current_location()) {
+            (DiagnosticFormat::Human, Some((line, span))) => SOURCE.with_borrow(|(_, source)| {
+                let e = GenericError::at(line, "", span, ErrorCode::InternalError, message)
+                    .with_note(format!("jilox panicked at {location}"))
+                    .with_note(report);
+                eprintln!("{}", e.render("internal error", source));
+            }),
+            (DiagnosticFormat::Human, None) => {
+                eprintln!("internal error[{}]: {message}", ErrorCode::InternalError);
+                eprintln!(" --> {location}");
+                eprintln!(" = note: {report}");
+            }
+            (DiagnosticFormat::Json, Some((line, span))) => SOURCE.with_borrow(|(file, source)| {
+                let snippet = source.lines().nth(line as usize).unwrap_or_default();
+                eprintln!(
+                    "{{\"code\": \"{}\", \"severity\": \"bug\", \"message\": {}, \"location\": {}, \"file\": {}, \"line\": {}, \"column\": {}, \"span\": [{}, {}], \"snippet\": {}}}",
+                    ErrorCode::InternalError,
+                    json::escape(message),
+                    json::escape(&location),
+                    json::escape(file),
+                    line + 1,
+                    span.column,
+                    span.start,
+                    span.end,
+                    json::escape(snippet)
+                )
+            }),
+            (DiagnosticFormat::Json, None) => eprintln!(
+                "{{\"code\": \"{}\", \"severity\": \"bug\", \"message\": {}, \"location\": {}}}",
+                ErrorCode::InternalError,
+                json::escape(message),
+                json::escape(&location)
+            ),
+        }
+        if env::var_os("RUST_BACKTRACE").is_some() {
+            default(info);
+        }
+    }));
+}
+
+/// The prelude is only picked up implicitly if the user actually created one.
+fn default_prelude() -> Option<PathBuf> {
+    let path = PathBuf::from(env::var_os("HOME")?).join(".config/rlox/prelude.lox");
+    path.is_file().then_some(path)
+}
+
+/// The contents of `file_name`, or `None` once the I/O error is reported.
+fn read_source(config: &Config, file_name: &str) -> Option<String> {
+    fs::read_to_string(file_name)
+        .map_err(|source| {
+            let e = LoxError::IoError {
+                path: file_name.to_string(),
+                source,
+            };
+            match config.diagnostics {
+                DiagnosticFormat::Human => eprintln!("error: {e}"),
+                DiagnosticFormat::Json => eprintln!(
+                    "{{\"code\": null, \"severity\": \"error\", \"message\": {}, \"file\": {}}}",
+                    json::escape(&e.to_string()),
+                    json::escape(file_name)
+                ),
+            }
+        })
+        .ok()
+}
 
-    if args.len() > 2 {
-        println!("Usage: jilox [script]");
-    } else if args.len() == 2 {
-        runFile(&args[1])?;
-    } else {
-        runPrompt()?;
+/// Runs `file_name` and reports what went wrong, if anything.
+fn run_source(
+    interpreter: &mut Interpreter,
+    config: &Config,
+    file_name: &str,
+    source: &str,
+) -> Result<Diagnostics> {
+    SOURCE.set((file_name.to_string(), source.to_string()));
+    let mut diagnostics = Diagnostics::new(&config.warnings);
+    handle(interpreter, config, source, &mut diagnostics);
+    report(config, file_name, &diagnostics, source);
+    exit_if_asked(interpreter)?;
+    Ok(diagnostics)
+}
+
+/// A broken prelude is reported, but the script or REPL still starts,
+/// just without whatever the prelude would have defined.
+fn run_prelude(interpreter: &mut Interpreter, config: &Config, file_name: &str) -> Result<()> {
+    let failed = match read_source(config, file_name) {
+        Some(source) => run_source(interpreter, config, file_name, &source)?.has_errors(),
+        None => true,
+    };
+    // Human diagnostics don't name their file, and these aren't the script's
+    if failed && config.diagnostics == DiagnosticFormat::Human {
+        eprintln!("note: the prelude {file_name} failed, continuing without it");
     }
+    Ok(())
+}
 
+fn run_file(interpreter: &mut Interpreter, config: &Config, file_name: &str) -> Result<()> {
+    let Some(source) = read_source(config, file_name) else {
+        process::exit(74);
+    };
+    let diagnostics = run_source(interpreter, config, file_name, &source)?;
+    if diagnostics.has_errors() {
+        // Same exit codes as the reference implementation
+        let runtime = diagnostics.iter().any(|d| d.error.code.is_runtime());
+        process::exit(if runtime { 70 } else { 65 });
+    }
     Ok(())
 }
 
-fn runFile(file_name: &str) -> Result<()> {
+/// What diagnostics name as the file for code typed into the REPL.
+const STDIN: &str = "<stdin>";
+
+fn run_prompt(interpreter: &mut Interpreter, config: &Config) -> Result<()> {
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        // NOTE: stdin is only locked while reading the line, the code we run
+        // may read from it too
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+        SOURCE.set((STDIN.to_string(), line.to_string()));
+        let mut diagnostics = Diagnostics::new(&config.warnings);
+        let value = handle(interpreter, config, line, &mut diagnostics);
+        // NOTE: errors in the REPL are reported, they should not end the session
+        report(config, STDIN, &diagnostics, line);
+        exit_if_asked(interpreter)?;
+        if let Some(value) = value {
+            println!("{value}");
+        }
+    }
     Ok(())
 }
 
-fn runPrompt() -> Result<()> {
+/// Ends the process if the script called `exit()`.
+fn exit_if_asked(interpreter: &Interpreter) -> Result<()> {
+    if let Some(code) = interpreter.exit_code {
+        io::stdout().flush()?;
+        process::exit(code);
+    }
     Ok(())
 }
+
+/// Does what `config.mode` asks for with `source`. Everything worth
+/// reporting ends up in `diagnostics`; in `Mode::Run` the value is returned.
+fn handle(
+    interpreter: &mut Interpreter,
+    config: &Config,
+    source: &str,
+    diagnostics: &mut Diagnostics,
+) -> Option<ast::LitKind> {
+    match config.mode {
+        Mode::Run => return run(interpreter, config, source, diagnostics),
+        Mode::Tokens => dump_tokens(source, diagnostics),
+        Mode::Ast | Mode::AstJson | Mode::AstDot => dump_ast(config, source, diagnostics),
+    }
+    None
+}
+
+/// `None` if the source could not be run, see `diagnostics` for why.
+fn run(
+    interpreter: &mut Interpreter,
+    config: &Config,
+    source: &str,
+    diagnostics: &mut Diagnostics,
+) -> Option<ast::LitKind> {
+    let expr = parse(config, source, diagnostics)?;
+    match interpreter.interpret(&expr) {
+        Ok(value) => Some(value),
+        Err(LoxError::Exit(code)) => {
+            interpreter.exit_code = Some(code);
+            None
+        }
+        Err(e) => {
+            diagnostics.error(e);
+            None
+        }
+    }
+}
+
+/// Scans and parses `source`, lints it, and applies the optimizations
+/// `config` asks for. `None` if anything along the way was an error.
+fn parse(config: &Config, source: &str, diagnostics: &mut Diagnostics) -> Option<ast::Expr> {
+    let (tokens, errors) = scanner::scan_tokens_recovering(source);
+    for e in errors {
+        diagnostics.error(e);
+    }
+    if diagnostics.has_errors() {
+        return None;
+    }
+    let mut expr = parser::parse_tokens(&tokens)
+        .map_err(|e| diagnostics.error(e))
+        .ok()?;
+    if cfg!(debug_assertions) {
+        invariants::check_tree(&expr);
+    }
+    for warning in lint::lint(&expr) {
+        diagnostics.warn(warning);
+    }
+    // Warnings can be errors too, with -Werror
+    if diagnostics.has_errors() {
+        return None;
+    }
+    if config.optimize {
+        expr = optimize::ConstantFolder::default().fold_expr(expr);
+        if cfg!(debug_assertions) {
+            invariants::check_tree(&expr);
+        }
+    }
+    Some(expr)
+}
+
+fn report(config: &Config, file: &str, diagnostics: &Diagnostics, source: &str) {
+    if diagnostics.is_empty() {
+        return;
+    }
+    match config.diagnostics {
+        DiagnosticFormat::Human => eprintln!("{}", diagnostics.render(source)),
+        DiagnosticFormat::Json => eprintln!("{}", diagnostics.to_json(file)),
+    }
+}
+
+/// Prints one token per line as `line:column type lexeme literal`, both
+/// 1-based as editors count them.
+/// Comments are kept, so the output mirrors the whole source.
+fn dump_tokens(source: &str, diagnostics: &mut Diagnostics) {
+    match scanner::scan_tokens_with_comments(source) {
+        Ok(tokens) => {
+            for token in tokens {
+                println!("{:>4}:{:<4} {}", token.line + 1, token.span.column, token);
+            }
+        }
+        Err(e) => diagnostics.error(e),
+    }
+}
+
+fn dump_ast(config: &Config, source: &str, diagnostics: &mut Diagnostics) {
+    let Some(expr) = parse(config, source, diagnostics) else {
+        return;
+    };
+    match config.mode {
+        Mode::AstJson => println!("{}", json::AstJson::serialize(&expr)),
+        Mode::AstDot => println!("{}", dot::AstDotExporter::export(&expr)),
+        _ => println!("{}", ast::PrettyPrinter::print(&expr)),
+    }
+}
@@ -0,0 +1,1078 @@
+use std::{
+    env,
+    f32::consts,
+    fmt, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    ast::{self, LitKind, LoxList, LoxMap, Value},
+    errors::{ErrorCode, GenericError, LoxError},
+    interpreter::Interpreter,
+    json,
+    scanner::Span,
+};
+
+/// What a native function runs: the interpreter that called it and the
+/// already evaluated arguments, as many as its arity says, or more if it
+/// is variadic.
+pub type NativeFn = dyn Fn(&mut Interpreter, &[Value]) -> Result<Value, LoxError>;
+
+/// A function implemented in Rust, callable from Lox like any other value.
+/// Two natives are only equal if they are the same function.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    /// Takes `arity` or more arguments.
+    pub variadic: bool,
+    func: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: impl ToString,
+        arity: usize,
+        func: impl Fn(&mut Interpreter, &[Value]) -> Result<Value, LoxError> + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            arity,
+            variadic: false,
+            func: Rc::new(func),
+        }
+    }
+
+    /// A native taking at least `arity` arguments.
+    pub fn variadic(
+        name: impl ToString,
+        arity: usize,
+        func: impl Fn(&mut Interpreter, &[Value]) -> Result<Value, LoxError> + 'static,
+    ) -> Self {
+        Self {
+            variadic: true,
+            ..Self::new(name, arity, func)
+        }
+    }
+
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[LitKind],
+    ) -> Result<LitKind, LoxError> {
+        (self.func)(interpreter, args)
+    }
+}
+
+/// The arguments of a host function as Rust values: a tuple like
+/// `(f64, String)` of types a value converts into. The tuple's length is
+/// the function's arity.
+pub trait FromArgs: Sized {
+    const ARITY: usize;
+
+    /// Converts exactly `ARITY` arguments.
+    fn from_args(args: &[LitKind]) -> Result<Self, LoxError>;
+}
+
+/// Argument `i`, converted. The error says which argument it was.
+fn convert<T>(i: usize, value: &LitKind) -> Result<T, LoxError>
+where
+    T: TryFrom<LitKind>,
+    LoxError: From<T::Error>,
+{
+    T::try_from(value.clone()).map_err(|e| match LoxError::from(e) {
+        LoxError::RuntimeError(e) => {
+            LoxError::RuntimeError(e.with_note(format!("in argument {}", i + 1)))
+        }
+        other => other,
+    })
+}
+
+macro_rules! impl_from_args {
+    ($arity:literal; $($i:tt $t:ident),*) => {
+        impl<$($t),*> FromArgs for ($($t,)*)
+        where
+            $($t: TryFrom<LitKind>, LoxError: From<$t::Error>,)*
+        {
+            const ARITY: usize = $arity;
+
+            #[allow(unused_variables)]
+            fn from_args(args: &[LitKind]) -> Result<Self, LoxError> {
+                Ok(($(convert::<$t>($i, &args[$i])?,)*))
+            }
+        }
+    };
+}
+
+impl_from_args!(0;);
+impl_from_args!(1; 0 A);
+impl_from_args!(2; 0 A, 1 B);
+impl_from_args!(3; 0 A, 1 B, 2 C);
+impl_from_args!(4; 0 A, 1 B, 2 C, 3 D);
+impl_from_args!(5; 0 A, 1 B, 2 C, 3 D, 4 E);
+impl_from_args!(6; 0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .field("variadic", &self.variadic)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+/// An error raised by a native function. Natives have no place in the
+/// source, so the interpreter points it at the call that failed.
+pub fn native_error(code: ErrorCode, message: &str) -> LoxError {
+    LoxError::RuntimeError(unplaced(code, message))
+}
+
+fn unplaced(code: ErrorCode, message: &str) -> GenericError {
+    GenericError::at(0, "", Span::default(), code, message)
+}
+
+/// Argument `i` of a call to `name`, which has to be a number.
+fn number(name: &str, args: &[LitKind], i: usize) -> Result<f32, LoxError> {
+    match args[i] {
+        LitKind::Number(n) => Ok(n),
+        ref other => Err(LoxError::RuntimeError(
+            unplaced(
+                ErrorCode::InvalidOperand,
+                &format!("{name} expects numbers"),
+            )
+            .with_note(format!("argument {} is a {}", i + 1, other.type_name())),
+        )),
+    }
+}
+
+/// Argument `i` of a call to `name`, which has to be a string.
+fn string<'a>(name: &str, args: &'a [LitKind], i: usize) -> Result<&'a str, LoxError> {
+    match &args[i] {
+        LitKind::String(s) => Ok(s),
+        other => Err(LoxError::RuntimeError(
+            unplaced(
+                ErrorCode::InvalidOperand,
+                &format!("{name} expects a string"),
+            )
+            .with_note(format!("argument {} is a {}", i + 1, other.type_name())),
+        )),
+    }
+}
+
+fn unary(name: &'static str, f: fn(f32) -> f32) -> NativeFunction {
+    NativeFunction::new(name, 1, move |_, args| {
+        Ok(LitKind::Number(f(number(name, args, 0)?)))
+    })
+}
+
+fn binary(name: &'static str, f: fn(f32, f32) -> f32) -> NativeFunction {
+    NativeFunction::new(name, 2, move |_, args| {
+        Ok(LitKind::Number(f(
+            number(name, args, 0)?,
+            number(name, args, 1)?,
+        )))
+    })
+}
+
+/// The functions every interpreter starts out with, as globals.
+pub fn natives() -> Vec<NativeFunction> {
+    let natives = vec![
+        NativeFunction::new("clock", 0, clock),
+        NativeFunction::new("elapsed", 0, elapsed),
+        NativeFunction::new("random", 0, random),
+        NativeFunction::new("randomInt", 2, random_int),
+        NativeFunction::new("randomSeed", 1, random_seed),
+        NativeFunction::new("readLine", 0, |_, _| {
+            Ok(read_line()?.map_or(LitKind::Nil, LitKind::String))
+        }),
+        NativeFunction::new("readNumber", 0, |_, _| {
+            Ok(read_line()?
+                .and_then(|line| parse_number(&line))
+                .map_or(LitKind::Nil, LitKind::Number))
+        }),
+        NativeFunction::new("env", 1, |_, args| {
+            let value = env::var(string("env", args, 0)?).ok();
+            Ok(value.map_or(LitKind::Nil, LitKind::String))
+        }),
+        NativeFunction::new("platform", 0, |_, _| {
+            Ok(LitKind::String(env::consts::OS.to_string()))
+        }),
+        NativeFunction::new("args", 0, |interpreter, _| {
+            let args = interpreter.args.iter().cloned().map(LitKind::String);
+            Ok(LitKind::List(LoxList::new(args.collect())))
+        }),
+        // Paths use the separators of the platform we run on
+        NativeFunction::variadic("pathJoin", 1, |_, args| {
+            let mut path = PathBuf::new();
+            for i in 0..args.len() {
+                path.push(string("pathJoin", args, i)?);
+            }
+            Ok(path_value(&path))
+        }),
+        NativeFunction::new("basename", 1, |_, args| {
+            let name = Path::new(string("basename", args, 0)?).file_name();
+            Ok(name.map_or(LitKind::Nil, |name| path_value(Path::new(name))))
+        }),
+        NativeFunction::new("dirname", 1, |_, args| {
+            let parent = Path::new(string("dirname", args, 0)?).parent();
+            Ok(parent.map_or(LitKind::Nil, path_value))
+        }),
+        NativeFunction::new("exists", 1, |interpreter, args| {
+            Ok(LitKind::Boolean(
+                fs_path(interpreter, "exists", args)?.exists(),
+            ))
+        }),
+        NativeFunction::new("listDir", 1, list_dir),
+        NativeFunction::new("exit", 1, exit),
+        NativeFunction::new("jsonParse", 1, |_, args| {
+            let data = serde_json::from_str(string("jsonParse", args, 0)?).map_err(|e| {
+                LoxError::RuntimeError(
+                    unplaced(ErrorCode::InvalidJson, "invalid JSON").with_note(e),
+                )
+            })?;
+            Ok(json::from_data(data))
+        }),
+        NativeFunction::new("jsonStringify", 1, |_, args| {
+            let data = json::to_data(&args[0]).map_err(|what| {
+                native_error(
+                    ErrorCode::InvalidJson,
+                    &format!("{what} can't be written as JSON"),
+                )
+            })?;
+            Ok(LitKind::String(data.to_string()))
+        }),
+        NativeFunction::variadic("format", 1, |_, args| {
+            Ok(LitKind::String(format(
+                string("format", args, 0)?,
+                &args[1..],
+            )?))
+        }),
+        // NOTE: there are no classes yet; instances will answer with their
+        // class name once there are
+        NativeFunction::new("type", 1, |_, args| {
+            Ok(LitKind::String(args[0].type_name().to_string()))
+        }),
+        NativeFunction::new("assert", 2, |_, args| match args[0].is_truthy() {
+            true => Ok(LitKind::Nil),
+            false => Err(native_error(
+                ErrorCode::AssertionFailed,
+                &args[1].to_string(),
+            )),
+        }),
+        NativeFunction::new("error", 1, |_, args| {
+            Err(native_error(ErrorCode::ScriptError, &args[0].to_string()))
+        }),
+        // Strings count code points, the same unit indexing uses
+        NativeFunction::new("len", 1, |_, args| {
+            let len = match &args[0] {
+                LitKind::String(s) => s.chars().count(),
+                LitKind::List(list) => list.len(),
+                LitKind::Map(map) => map.len(),
+                LitKind::Range(range) => range.len(),
+                other => {
+                    return Err(LoxError::RuntimeError(
+                        unplaced(
+                            ErrorCode::InvalidOperand,
+                            "len expects a string, list, map or range",
+                        )
+                        .with_note(format!("argument 1 is a {}", other.type_name())),
+                    ))
+                }
+            };
+            Ok(LitKind::Number(len as f32))
+        }),
+        NativeFunction::new("formatNumber", 3, format_number),
+        NativeFunction::new("csvParse", 1, csv_parse),
+        NativeFunction::new("csvStringify", 1, csv_stringify),
+        unary("abs", f32::abs),
+        unary("floor", f32::floor),
+        unary("ceil", f32::ceil),
+        // Halfway cases round away from zero, so round(-2.5) is -3
+        unary("round", f32::round),
+        unary("sqrt", f32::sqrt),
+        unary("sin", f32::sin),
+        unary("cos", f32::cos),
+        binary("pow", f32::powf),
+        binary("min", f32::min),
+        binary("max", f32::max),
+    ];
+    #[cfg(feature = "regex")]
+    let natives = [natives, re::natives()].concat();
+    natives
+}
+
+/// `reMatch`, `reFindAll` and `reReplace`, with the `regex` feature. A match
+/// is a list of the whole match and then each capture group, `nil` for a
+/// group that took no part in it.
+#[cfg(feature = "regex")]
+mod re {
+    use regex::{Captures, Regex};
+
+    use super::*;
+
+    pub fn natives() -> Vec<NativeFunction> {
+        vec![
+            NativeFunction::new("reMatch", 2, |_, args| {
+                let re = compile("reMatch", args)?;
+                let text = string("reMatch", args, 1)?;
+                Ok(re.captures(text).map_or(LitKind::Nil, groups))
+            }),
+            NativeFunction::new("reFindAll", 2, |_, args| {
+                let re = compile("reFindAll", args)?;
+                let text = string("reFindAll", args, 1)?;
+                Ok(LitKind::List(LoxList::new(
+                    re.captures_iter(text).map(groups).collect(),
+                )))
+            }),
+            // `$1` or `${name}` in the replacement stand for a group
+            NativeFunction::new("reReplace", 3, |_, args| {
+                let re = compile("reReplace", args)?;
+                let text = string("reReplace", args, 1)?;
+                let replacement = string("reReplace", args, 2)?;
+                Ok(LitKind::String(
+                    re.replace_all(text, replacement).into_owned(),
+                ))
+            }),
+        ]
+    }
+
+    fn groups(captures: Captures) -> LitKind {
+        let groups = captures
+            .iter()
+            .map(|group| group.map_or(LitKind::Nil, |m| m.as_str().into()));
+        LitKind::List(LoxList::new(groups.collect()))
+    }
+
+    /// The pattern `name` was called with. When it doesn't compile, the note
+    /// says what is wrong and which columns of the pattern it is about.
+    fn compile(name: &str, args: &[LitKind]) -> Result<Regex, LoxError> {
+        let pattern = string(name, args, 0)?;
+        Regex::new(pattern).map_err(|e| {
+            let span = match regex_syntax::Parser::new().parse(pattern) {
+                Err(regex_syntax::Error::Parse(e)) => Some((e.kind().to_string(), *e.span())),
+                Err(regex_syntax::Error::Translate(e)) => Some((e.kind().to_string(), *e.span())),
+                _ => None,
+            };
+            let note = match span {
+                Some((what, span)) => format!(
+                    "{what}, at columns {}-{} of \"{pattern}\"",
+                    span.start.column, span.end.column
+                ),
+                // Valid, but too big to compile
+                None => e.to_string(),
+            };
+            LoxError::RuntimeError(
+                unplaced(ErrorCode::InvalidRegex, "invalid regular expression").with_note(note),
+            )
+        })
+    }
+}
+
+/// The constants every interpreter starts out with, as globals.
+pub const CONSTANTS: &[(&str, f32)] = &[("PI", consts::PI), ("E", consts::E)];
+
+/// Seconds since the Unix epoch.
+// NOTE: Numbers are 32-bit for now, which can only tell apart times about
+// two minutes apart this far from the epoch. Differences between two calls
+// become useful once numbers are 64-bit; until then `elapsed()` is the one
+// to time code with.
+fn clock(_: &mut Interpreter, _: &[Value]) -> Result<Value, LoxError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("the clock is set after 1970");
+    Ok(LitKind::Number(now.as_secs_f32()))
+}
+
+/// Seconds since the interpreter started, on a clock that never jumps.
+// NOTE: Small numbers keep their precision: a microsecond for the first
+// few seconds, a millisecond for hours.
+fn elapsed(interpreter: &mut Interpreter, _: &[Value]) -> Result<Value, LoxError> {
+    Ok(LitKind::Number(interpreter.started.elapsed().as_secs_f32()))
+}
+
+/// Most digits `formatNumber` shows after the point, more than an f32 has.
+const MAX_DECIMALS: f32 = 20.;
+
+/// `formatNumber(1234.5, 2, ",")` is `"1,234.50"`.
+fn format_number(_: &mut Interpreter, args: &[Value]) -> Result<Value, LoxError> {
+    let n = number("formatNumber", args, 0)?;
+    let decimals = number("formatNumber", args, 1)?;
+    if decimals.fract() != 0.0 || !(0.0..=MAX_DECIMALS).contains(&decimals) {
+        return Err(LoxError::RuntimeError(
+            unplaced(ErrorCode::InvalidOperand, "invalid number of decimals").with_note(format!(
+                "it has to be a whole number from 0 to {MAX_DECIMALS}, got {decimals}"
+            )),
+        ));
+    }
+    let sep = string("formatNumber", args, 2)?;
+    Ok(LitKind::String(ast::format_number(
+        n,
+        decimals as usize,
+        sep,
+    )))
+}
+
+fn invalid_csv(message: &str, note: impl ToString) -> LoxError {
+    LoxError::RuntimeError(unplaced(ErrorCode::InvalidCsv, message).with_note(note))
+}
+
+/// `csvParse(text)`: a map per row, keyed by the fields of the header row.
+/// Fields stay strings; every row needs as many as the header has.
+fn csv_parse(_: &mut Interpreter, args: &[Value]) -> Result<Value, LoxError> {
+    let text = string("csvParse", args, 0)?;
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    let header = reader
+        .headers()
+        .map_err(|e| invalid_csv("invalid CSV", e))?
+        .clone();
+    let rows = reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(|e| invalid_csv("invalid CSV", e))?;
+            let mut row = LoxMap::default();
+            for (key, field) in header.iter().zip(&record) {
+                row.insert(key.into(), field.into());
+            }
+            Ok(LitKind::Map(row))
+        })
+        .collect::<Result<_, LoxError>>()?;
+    Ok(LitKind::List(LoxList::new(rows)))
+}
+
+/// A value as a CSV field: strings as they are, nil as an empty field.
+fn csv_field(value: &LitKind) -> Option<String> {
+    match value {
+        LitKind::String(s) => Some(s.clone()),
+        LitKind::Nil => Some(String::new()),
+        LitKind::Number(_) | LitKind::Boolean(_) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// `csvStringify(rows)`, the other way around: the keys of the first map
+/// make the header, and a row missing one of them gets an empty field.
+fn csv_stringify(_: &mut Interpreter, args: &[Value]) -> Result<Value, LoxError> {
+    let LitKind::List(rows) = &args[0] else {
+        return Err(native_error(
+            ErrorCode::InvalidOperand,
+            "csvStringify expects a list of maps",
+        ));
+    };
+    let rows = rows
+        .iter()
+        .map(|row| match row {
+            LitKind::Map(map) => Ok(map),
+            other => Err(invalid_csv(
+                "csvStringify expects a list of maps",
+                format!("a row is a {}", other.type_name()),
+            )),
+        })
+        .collect::<Result<Vec<_>, LoxError>>()?;
+    let Some(first) = rows.first() else {
+        return Ok(LitKind::String(String::new()));
+    };
+    let header: Vec<&LitKind> = first.iter().map(|(key, _)| key).collect();
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let mut write = |fields: Vec<String>| {
+        writer
+            .write_record(fields)
+            .expect("writing to memory can't fail")
+    };
+    let unwritable = |value: &LitKind| {
+        invalid_csv(
+            "value can't be written as CSV",
+            format!("{value} is a {}", value.type_name()),
+        )
+    };
+    write(
+        header
+            .iter()
+            .map(|key| csv_field(key).expect("keys are strings or numbers"))
+            .collect(),
+    );
+    for (i, row) in rows.iter().enumerate() {
+        if let Some((key, _)) = row.iter().find(|(key, _)| first.get(key).is_none()) {
+            return Err(invalid_csv(
+                "rows have different columns",
+                format!("row {} has {key}, which the first row doesn't", i + 1),
+            ));
+        }
+        let fields = header
+            .iter()
+            .map(|key| {
+                let value = row.get(key).unwrap_or(&LitKind::Nil);
+                csv_field(value).ok_or_else(|| unwritable(value))
+            })
+            .collect::<Result<_, LoxError>>()?;
+        write(fields);
+    }
+    let bytes = writer.into_inner().expect("writing to memory can't fail");
+    Ok(LitKind::String(
+        String::from_utf8(bytes).expect("the fields were strings"),
+    ))
+}
+
+/// Replaces every `{}` in `template` with the next value, shown the same
+/// way the REPL shows it. `{{` and `}}` stand for literal braces.
+fn format(template: &str, values: &[LitKind]) -> Result<String, LoxError> {
+    let invalid = |message: &str| native_error(ErrorCode::InvalidFormat, message);
+    let mut out = String::new();
+    let mut values = values.iter();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                out.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                let value = values
+                    .next()
+                    .ok_or_else(|| invalid("more placeholders than values"))?;
+                out += &value.to_string();
+            }
+            ('{' | '}', _) => return Err(invalid(&format!("unmatched `{c}` in format string"))),
+            _ => out.push(c),
+        }
+    }
+    match values.next() {
+        Some(_) => Err(invalid("more values than placeholders")),
+        None => Ok(out),
+    }
+}
+
+fn path_value(path: &Path) -> LitKind {
+    LitKind::String(path.to_string_lossy().into_owned())
+}
+
+/// The path `name` was called with, if scripts may look at the file system.
+fn fs_path<'a>(
+    interpreter: &Interpreter,
+    name: &str,
+    args: &'a [LitKind],
+) -> Result<&'a Path, LoxError> {
+    if !interpreter.allow_fs {
+        return Err(LoxError::RuntimeError(
+            unplaced(
+                ErrorCode::FsNotAllowed,
+                &format!("{name} needs access to the file system"),
+            )
+            .with_note("run jilox with --allow-fs to grant it"),
+        ));
+    }
+    Ok(Path::new(string(name, args, 0)?))
+}
+
+/// The names of the entries in a directory, sorted so scripts see the
+/// same order on every platform.
+fn list_dir(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, LoxError> {
+    let path = fs_path(interpreter, "listDir", args)?;
+    let failed = |e: io::Error| {
+        native_error(
+            ErrorCode::FsFailed,
+            &format!("could not list {}: {e}", path.display()),
+        )
+    };
+    let mut names = fs::read_dir(path)
+        .map_err(failed)?
+        .map(|entry| Ok(entry.map_err(failed)?.file_name()))
+        .collect::<Result<Vec<_>, LoxError>>()?;
+    names.sort();
+    let names = names.iter().map(|name| path_value(Path::new(name)));
+    Ok(LitKind::List(LoxList::new(names.collect())))
+}
+
+/// Stops the script, and the process with status `code`.
+fn exit(_: &mut Interpreter, args: &[Value]) -> Result<Value, LoxError> {
+    let code = number("exit", args, 0)?;
+    // `as i64` saturates, so anything out of range fails the conversion
+    let code = Some(code)
+        .filter(|c| c.fract() == 0.0)
+        .and_then(|c| i32::try_from(c as i64).ok());
+    match code {
+        Some(code) => Err(LoxError::Exit(code)),
+        None => Err(native_error(
+            ErrorCode::NotAnInteger,
+            "exit code is not an integer",
+        )),
+    }
+}
+
+/// The random numbers behind `random()` and friends: SplitMix64, which is
+/// small, fast and good enough for games and simulations, but not for
+/// anything that needs to be unpredictable.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seeded from the clock, so every run is different.
+    pub fn from_time() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("the clock is set after 1970");
+        Self::new(now.as_nanos() as u64)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..1`, using as many bits as a number has mantissa.
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// A number in `0..1`.
+fn random(interpreter: &mut Interpreter, _: &[Value]) -> Result<Value, LoxError> {
+    Ok(LitKind::Number(interpreter.rng.next_f32()))
+}
+
+/// A whole number from `lo` to `hi`, both included, so `randomInt(1, 6)`
+/// rolls a die.
+fn random_int(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, LoxError> {
+    let (lo, hi) = (number("randomInt", args, 0)?, number("randomInt", args, 1)?);
+    // Whole numbers that fit an i64, so the arithmetic below is exact
+    let whole =
+        |n: f32| (n.is_finite() && n.fract() == 0.0 && n.abs() < 2f32.powi(63)).then_some(n as i64);
+    let count = whole(lo)
+        .zip(whole(hi))
+        .filter(|(lo, hi)| lo <= hi)
+        .and_then(|(lo, hi)| hi.checked_sub(lo)?.checked_add(1));
+    let (Some(lo), Some(count)) = (whole(lo), count) else {
+        return Err(LoxError::RuntimeError(
+            unplaced(ErrorCode::InvalidOperand, "invalid range for randomInt").with_note(format!(
+                "the bounds have to be whole numbers less than 2^63 apart with lo <= hi, got {lo} and {hi}"
+            )),
+        ));
+    };
+    Ok(LitKind::Number(
+        (lo + (interpreter.rng.next_u64() % count as u64) as i64) as f32,
+    ))
+}
+
+/// Makes the following random numbers the same on every run.
+fn random_seed(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, LoxError> {
+    interpreter.rng = Rng::new(number("randomSeed", args, 0)?.to_bits() as u64);
+    Ok(LitKind::Nil)
+}
+
+/// The next line of stdin without its line ending, `None` at the end of
+/// input.
+// NOTE: The REPL reads its own lines through the same stdin, one at a time,
+// and never holds the lock while running code, so a script reading input
+// there just takes the next line typed.
+fn read_line() -> Result<Option<String>, LoxError> {
+    // Whatever the script printed so far is probably the prompt
+    let _ = io::stdout().flush();
+    next_line(&mut io::stdin().lock()).map_err(|e| {
+        native_error(
+            ErrorCode::InputFailed,
+            &format!("could not read from stdin: {e}"),
+        )
+    })
+}
+
+fn next_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(Some(line))
+}
+
+/// A number the way it would be written in a script, `None` for anything
+/// else, so scripts can ask again.
+fn parse_number(line: &str) -> Option<f32> {
+    let line = line.trim();
+    let digits = line.strip_prefix('-').unwrap_or(line);
+    let looks_like_number = digits.starts_with(|c: char| c.is_ascii_digit())
+        && digits.bytes().all(|b| b.is_ascii_digit() || b == b'.');
+    looks_like_number.then(|| line.parse().ok()).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+    use crate::{parser::parse_tokens, scanner::scan_tokens};
+
+    fn eval(source: &str) -> Result<LitKind, LoxError> {
+        let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+        Interpreter::new().interpret(&expr)
+    }
+
+    /// Checks that `source` fails at runtime with `code`, and returns the
+    /// error for any further checks.
+    fn assert_runtime_error(source: &str, code: ErrorCode) -> GenericError {
+        match eval(source) {
+            Err(LoxError::RuntimeError(e)) => {
+                assert_eq!(e.code, code, "{source}");
+                e
+            }
+            other => panic!("{source}: expected a runtime error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = &natives()[0];
+        let Ok(LitKind::Number(now)) = clock.call(&mut Interpreter::new(), &[]) else {
+            panic!("clock() should return a number");
+        };
+        // Some time after this was written
+        assert!(now > 1.7e9);
+        assert_eq!(clock.to_string(), "<native fn clock>");
+    }
+
+    #[test]
+    fn test_elapsed() {
+        let mut interpreter = Interpreter::new();
+        let mut elapsed = || match elapsed(&mut interpreter, &[]) {
+            Ok(LitKind::Number(n)) => n,
+            other => panic!("elapsed() should return a number, got {other:?}"),
+        };
+        let start = elapsed();
+        thread::sleep(Duration::from_millis(10));
+        let took = elapsed() - start;
+        // Precise enough to time a short piece of code
+        assert!(start < 1.0 && (0.01..1.0).contains(&took), "{took}");
+    }
+
+    #[test]
+    fn test_random() {
+        let mut interpreter = Interpreter::new();
+        let mut rolls = |source: &str| -> Vec<LitKind> {
+            let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+            (0..100)
+                .map(|_| interpreter.interpret(&expr).unwrap())
+                .collect()
+        };
+        let seeded = "[randomSeed(42), random(), randomInt(1, 6)]";
+        assert_eq!(rolls(seeded), rolls(seeded));
+        for roll in rolls("randomInt(-1, 1)") {
+            assert!(matches!(roll, LitKind::Number(n) if [-1., 0., 1.].contains(&n)));
+        }
+        for n in rolls("random()") {
+            assert!(matches!(n, LitKind::Number(n) if (0.0..1.0).contains(&n)));
+        }
+        assert!(eval("randomInt(2, 1)").is_err());
+        assert!(eval("randomInt(0.5, 1)").is_err());
+        for source in [
+            "randomInt(0, pow(10, 30))",
+            "randomInt(-pow(2, 62) * 1.5, pow(2, 62) * 1.5)",
+            "randomInt(0, pow(10, 100))",
+        ] {
+            assert_runtime_error(source, ErrorCode::InvalidOperand);
+        }
+        let huge = eval("randomInt(-pow(2, 61), pow(2, 61))").unwrap();
+        assert!(matches!(huge, LitKind::Number(n) if n.abs() <= 2f32.powi(61)));
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut input = io::Cursor::new("42\r\n  -1.5 \nabc\n\nlast");
+        let lines: Vec<_> = std::iter::from_fn(|| next_line(&mut input).unwrap()).collect();
+        assert_eq!(lines, ["42", "  -1.5 ", "abc", "", "last"]);
+        let numbers: Vec<_> = lines.iter().map(|l| parse_number(l)).collect();
+        assert_eq!(numbers, [Some(42.), Some(-1.5), None, None, None]);
+        assert_eq!(parse_number("1e3"), None);
+        assert_eq!(parse_number("inf"), None);
+    }
+
+    #[test]
+    fn test_os() {
+        let expr = parse_tokens(&scan_tokens("[args(), platform()]").unwrap()).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.args = vec!["-v".to_string()];
+        let value = interpreter.interpret(&expr).unwrap();
+        assert_eq!(
+            value.to_string(),
+            format!("[[\"-v\"], \"{}\"]", env::consts::OS)
+        );
+        assert_eq!(eval("env(\"JILOX_SURELY_UNSET\")").unwrap(), LitKind::Nil);
+        assert!(matches!(eval("env(\"PATH\")"), Ok(LitKind::String(_))));
+        assert!(eval("env(1)").is_err());
+    }
+
+    #[test]
+    fn test_exit() {
+        assert!(matches!(eval("1 + exit(3)"), Err(LoxError::Exit(3))));
+        assert!(matches!(eval("exit(-1)"), Err(LoxError::Exit(-1))));
+        assert!(matches!(
+            eval("exit(-2147483648)"),
+            Err(LoxError::Exit(i32::MIN))
+        ));
+        for source in [
+            "exit(1.5)",
+            "exit(2147483648)",
+            "exit(-2147483904)",
+            "exit(pow(10, 100))",
+        ] {
+            assert_runtime_error(source, ErrorCode::NotAnInteger);
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let value = eval("format(\"x={}, y={} {{{}}}\", 1.5, \"a\", [nil])");
+        assert_eq!(value.unwrap().to_string(), "x=1.5, y=a {[nil]}");
+        assert_eq!(eval("format(\"}}\")").unwrap().to_string(), "}");
+        for source in [
+            "format(\"{}\")",
+            "format(\"\", 1)",
+            "format(\"{\")",
+            "format(1)",
+        ] {
+            assert!(eval(source).is_err(), "{source}");
+        }
+        let e = assert_runtime_error("format()", ErrorCode::WrongArity);
+        assert_eq!(
+            e.to_string(),
+            "line 0, \"(\": [E3007] format expects at least 1 arguments but got 0"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        // Lox strings have no escapes, so the text can't be written in one
+        let text = r#"{"b": [1, 2.5, -0.1, true, null], "a": {"": "\u00e9"}}"#;
+        let mut interpreter = Interpreter::new();
+        interpreter.define("text", LitKind::String(text.to_string()));
+        let mut eval_text = |source: &str| {
+            let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+            interpreter.interpret(&expr).unwrap().to_string()
+        };
+        assert_eq!(
+            eval_text("jsonParse(text)"),
+            r#"{"b": [1, 2.5, -0.1, true, nil], "a": {"": "é"}}"#
+        );
+        assert_eq!(
+            eval_text("jsonStringify(jsonParse(text))"),
+            r#"{"b":[1,2.5,-0.1,true,null],"a":{"":"é"}}"#
+        );
+        assert_eq!(
+            eval("jsonStringify({1: \"x\"})").unwrap().to_string(),
+            r#"{"1":"x"}"#
+        );
+        for source in [
+            "jsonParse(\"[1,\")",
+            "jsonStringify(0..1)",
+            "jsonStringify([clock])",
+        ] {
+            assert!(eval(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_type() {
+        let value = eval("[type(1), type(\"\"), type(nil), type(true), type([]), type({}), type(0..1), type(type)]");
+        assert_eq!(
+            value.unwrap().to_string(),
+            r#"["number", "string", "nil", "boolean", "list", "map", "range", "function"]"#
+        );
+    }
+
+    #[test]
+    fn test_assert() {
+        assert_eq!(eval("assert(1 < 2, \"math\")").unwrap(), LitKind::Nil);
+        for (source, code) in [
+            ("assert(nil, \"no value\")", ErrorCode::AssertionFailed),
+            ("1 + error(\"no value\")", ErrorCode::ScriptError),
+        ] {
+            let e = assert_runtime_error(source, code);
+            assert_eq!(e.to_string(), format!("line 0, \"(\": [{code}] no value"));
+        }
+    }
+
+    #[test]
+    fn test_math() {
+        for (source, want) in [
+            ("abs(-2.5)", 2.5),
+            ("floor(-1.5)", -2.),
+            ("ceil(1.2)", 2.),
+            ("round(2.5) + round(-2.5)", 0.),
+            ("sqrt(16)", 4.),
+            ("pow(2, 10)", 1024.),
+            ("min(3, -1) + max(3, -1)", 2.),
+            ("cos(0) - sin(0)", 1.),
+            ("round(sin(PI / 2) * E * 1000)", 2718.),
+        ] {
+            assert_eq!(eval(source).unwrap(), LitKind::Number(want), "{source}");
+        }
+        let e = assert_runtime_error("max(1, \"2\")", ErrorCode::InvalidOperand);
+        assert_eq!(e.span.start, 3);
+    }
+
+    #[test]
+    fn test_len() {
+        for (source, want) in [
+            ("len(\"\")", 0.),
+            ("len(\"héllo\")", 5.),
+            ("len(\"🇷🇴\")", 2.),
+            ("len([1, [2, 3]])", 2.),
+            ("len({1: 2, \"a\": nil})", 2.),
+            ("len(2..5)", 3.),
+            ("len(5..2)", 0.),
+        ] {
+            assert_eq!(eval(source).unwrap(), LitKind::Number(want), "{source}");
+        }
+        assert_runtime_error("len(3)", ErrorCode::InvalidOperand);
+    }
+
+    #[test]
+    fn test_format_number() {
+        let value = eval("[formatNumber(1234567.5, 2, \",\"), formatNumber(-999.5, 0, \".\"), formatNumber(12, 3, \"\")]");
+        assert_eq!(
+            value.unwrap().to_string(),
+            r#"["1,234,567.50", "-1.000", "12.000"]"#
+        );
+        let value = eval("[formatNumber(-0.001, 2, \",\"), formatNumber(-0.4, 0, \"\")]");
+        assert_eq!(value.unwrap().to_string(), r#"["0.00", "0"]"#);
+        for source in [
+            "formatNumber(1, -1, \",\")",
+            "formatNumber(1, 1.5, \",\")",
+            "formatNumber(1, 100, \",\")",
+            "formatNumber(1, 2, 3)",
+            "formatNumber(\"1\", 2, \",\")",
+        ] {
+            assert_runtime_error(source, ErrorCode::InvalidOperand);
+        }
+    }
+
+    #[test]
+    fn test_paths() {
+        let joined = Path::new("a").join("b").join("c.txt");
+        assert_eq!(
+            eval("pathJoin(\"a\", \"b\", \"c.txt\")").unwrap(),
+            path_value(&joined)
+        );
+        // Lox strings have no escapes, so this is the path as it is
+        let path = format!("\"{}\"", joined.display());
+        let value = eval(&format!("[basename({path}), dirname({path})]")).unwrap();
+        let dir = Path::new("a").join("b");
+        assert_eq!(
+            value.to_string(),
+            format!("[\"c.txt\", \"{}\"]", dir.display())
+        );
+        assert_eq!(eval("basename(\"\")").unwrap(), LitKind::Nil);
+        assert_eq!(eval("dirname(\"\")").unwrap(), LitKind::Nil);
+        assert!(eval("pathJoin(\"a\", 1)").is_err());
+    }
+
+    #[test]
+    fn test_fs() {
+        let dir = env::temp_dir().join(format!("jilox-test-fs-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("b.txt"), "").unwrap();
+        let path = format!("\"{}\"", dir.display());
+        let source = format!("[exists({path}), listDir({path}), exists(pathJoin({path}, \"no\"))]");
+        assert_runtime_error(&source, ErrorCode::FsNotAllowed);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.allow_fs = true;
+        let mut run = |source: &str| {
+            interpreter.interpret(&parse_tokens(&scan_tokens(source).unwrap()).unwrap())
+        };
+        assert_eq!(
+            run(&source).unwrap().to_string(),
+            "[true, [\"b.txt\", \"sub\"], false]"
+        );
+        let err = run(&format!("listDir(pathJoin({path}, \"no\"))")).unwrap_err();
+        assert!(matches!(err, LoxError::RuntimeError(e) if e.code == ErrorCode::FsFailed));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_csv() {
+        // Lox strings can't hold a `"`, so the text comes in as a global
+        let text = "name,age\nann,3\n\"b, c\",\n";
+        let mut interpreter = Interpreter::new();
+        interpreter.define("text", LitKind::from(text));
+        let mut run = |source: &str| {
+            let expr = parse_tokens(&scan_tokens(source).unwrap()).unwrap();
+            interpreter.interpret(&expr).unwrap()
+        };
+        assert_eq!(
+            run("csvParse(text)").to_string(),
+            r#"[{"name": "ann", "age": "3"}, {"name": "b, c", "age": ""}]"#
+        );
+        assert_eq!(run("csvStringify(csvParse(text))"), LitKind::from(text));
+        assert_eq!(
+            eval("csvStringify([{1: true, \"b\": nil}, {\"b\": 2.5}])").unwrap(),
+            LitKind::from("1,b\ntrue,\n,2.5\n")
+        );
+        assert_eq!(eval("csvStringify([])").unwrap(), LitKind::from(""));
+        for source in [
+            "csvParse(\"a,b\n1\")",
+            "csvStringify([1])",
+            "csvStringify([{\"a\": [1]}])",
+            "csvStringify([{\"a\": 1}, {\"b\": 2}])",
+        ] {
+            assert_runtime_error(source, ErrorCode::InvalidCsv);
+        }
+        assert!(eval("csvStringify(\"a\")").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn test_regex() {
+        assert_eq!(
+            eval("reMatch(\"(\\w+)@(\\w+)?\", \"mail ann@ now\")")
+                .unwrap()
+                .to_string(),
+            r#"["ann@", "ann", nil]"#
+        );
+        assert_eq!(eval("reMatch(\"x\", \"abc\")").unwrap(), LitKind::Nil);
+        assert_eq!(
+            eval("reFindAll(\"(\\d)(\\d)\", \"12 345\")")
+                .unwrap()
+                .to_string(),
+            r#"[["12", "1", "2"], ["34", "3", "4"]]"#
+        );
+        assert_eq!(
+            eval("reReplace(\"(\\w+) (\\w+)\", \"hello world\", \"$2 $1\")").unwrap(),
+            LitKind::from("world hello")
+        );
+        let source = "1 + reMatch(\"a(b\", \"ab\")";
+        let e = assert_runtime_error(source, ErrorCode::InvalidRegex);
+        assert_eq!(e.span.start, 11);
+        assert!(
+            e.render("error", source)
+                .ends_with("= note: unclosed group, at columns 2-3 of \"a(b\""),
+            "{}",
+            e.render("error", source)
+        );
+    }
+}
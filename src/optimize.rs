@@ -0,0 +1,110 @@
+use crate::{
+    ast::{fold_children, BinOp, Expr, ExprKind, Folder, LitKind},
+    interpreter::Interpreter,
+};
+
+/*
+* NOTE: Constant folding. Once a node's children are all literals, the
+* node is evaluated by the interpreter itself, so folding can never
+* disagree with running the program. If evaluating it fails, the node is
+* kept as is and fails at runtime, exactly where it would have without
+* --optimize. Division by zero is never folded: it should reach the
+* runtime rather than turn into a literal.
+*/
+
+/// Folds with one interpreter, built once rather than for every node.
+#[derive(Default)]
+pub struct ConstantFolder {
+    interpreter: Interpreter,
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    matches!(expr.kind, ExprKind::Literal(_))
+}
+
+impl Folder for ConstantFolder {
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        let mut expr = fold_children(self, expr);
+        let foldable = match &expr.kind {
+            // Natives may have side effects, or, like clock(), not return
+            // the same value twice. A spread stands for several arguments,
+            // which no single literal can. Match arms were folded on their
+            // own, and the match itself is rarely on literals.
+            ExprKind::Literal(_)
+            | ExprKind::Variable(_)
+            | ExprKind::Call(..)
+            | ExprKind::Spread(_)
+            | ExprKind::Match(..) => false,
+            ExprKind::Grouping(inner) | ExprKind::Unary(inner, _) => is_literal(inner),
+            ExprKind::Binary(_, right, BinOp::Slash | BinOp::Percent) if matches!(right.kind, ExprKind::Literal(LitKind::Number(n)) if n == 0.0) => {
+                false
+            }
+            ExprKind::Binary(left, right, _) | ExprKind::Index(left, right) => {
+                is_literal(left) && is_literal(right)
+            }
+            ExprKind::List(items) => items.iter().all(is_literal),
+            ExprKind::Slice(target, start, end) => {
+                is_literal(target) && [start, end].into_iter().flatten().all(|e| is_literal(e))
+            }
+            ExprKind::Map(entries) => entries.iter().all(|(k, v)| is_literal(k) && is_literal(v)),
+        };
+        if !foldable {
+            return expr;
+        }
+        match self.interpreter.interpret(&expr) {
+            Ok(value) => {
+                expr.kind = ExprKind::Literal(value);
+                expr.depth = 1;
+                expr
+            }
+            Err(_) => expr,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::PrettyPrinter, parser::parse_tokens, scanner::scan_tokens};
+
+    fn parse(source: &str) -> Expr {
+        parse_tokens(&scan_tokens(source).unwrap()).unwrap()
+    }
+
+    fn fold(source: &str) -> String {
+        PrettyPrinter::print(&ConstantFolder::default().fold_expr(parse(source)))
+    }
+
+    #[test]
+    fn test_folds_constants() {
+        assert_eq!(fold("2 * (3 + 4)"), "14");
+        assert_eq!(fold("!true"), "false");
+        assert_eq!(fold("\"a\" + (\"b\")"), "\"ab\"");
+    }
+
+    #[test]
+    fn test_keeps_what_fails_at_runtime() {
+        assert_eq!(fold("1 / (2 - 2)"), "( / 1 0 )");
+        assert_eq!(fold("(1 + 1) + nil"), "( + 2 nil )");
+    }
+
+    #[test]
+    fn test_preserves_semantics() {
+        for source in [
+            "1 + 2 * 3 - 4 / 8",
+            "-(1 - 3) * 2",
+            "1 / 0",
+            "\"a\" + 1",
+            "!nil",
+        ] {
+            let plain = Interpreter::new().interpret(&parse(source));
+            let folded =
+                Interpreter::new().interpret(&ConstantFolder::default().fold_expr(parse(source)));
+            assert_eq!(
+                plain.map(|v| v.to_string()).map_err(|e| e.to_string()),
+                folded.map(|v| v.to_string()).map_err(|e| e.to_string()),
+                "{source}"
+            );
+        }
+    }
+}
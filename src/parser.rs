@@ -1,23 +1,60 @@
 use std::iter::Peekable;
 
 use crate::{
-    ast::{BinOp, Expr, ExprKind, LitKind, UnOp},
-    errors::{GenericError, LoxError},
+    ast::{with_stack, BinOp, Expr, ExprKind, LitKind, Pattern, UnOp},
+    errors::{ErrorCode, GenericError, LoxError},
     scanner::{Token, TokenType},
 };
 
 /*
-*    expression     → equality ;
+*    program        → expression EOF ;
+*    expression     → coalesce ;
+*    coalesce       → equality ( "??" equality )* ;
 *    equality       → comparison ( ( "!=" | "==" ) comparison )* ;
-*    comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+*    comparison     → range ( ( ">" | ">=" | "<" | "<=" ) range )* ;
+*    range          → bit_or ( ( ".." | "..=" ) bit_or )* ;
+*    bit_or         → bit_xor ( "|" bit_xor )* ;
+*    bit_xor        → bit_and ( "^" bit_and )* ;
+*    bit_and        → shift ( "&" shift )* ;
+*    shift          → term ( ( "<<" | ">>" ) term )* ;
 *    term           → factor ( ( "-" | "+" ) factor )* ;
-*    factor         → unary ( ( "/" | "*" ) unary )* ;
-*    unary          → ( "!" | "-" ) unary
-*                   | primary ;
-*    primary        → NUMBER | STRING | "true" | "false" | "nil"
-*                   | "(" expression ")" ;
+*    factor         → unary ( ( "/" | "*" | "%" ) unary )* ;
+*    unary          → ( "!" | "-" | "~" ) unary
+*                   | power ;
+*    power          → call ( "**" unary )? ;
+*    call           → primary ( "[" subscript "]" | "(" arguments? ")" )* ;
+*    subscript      → expression | expression? ":" expression? ;
+*    arguments      → argument ( "," argument )* ;
+*    argument       → "..."? expression ;
+*    primary        → NUMBER | STRING | "true" | "false" | "nil" | IDENTIFIER
+*                   | "(" expression ")"
+*                   | "[" ( expression ( "," expression )* )? "]"
+*                   | "{" ( entry ( "," entry )* )? "}"
+*                   | match ;
+*    entry          → expression ":" expression ;
+*    match          → "match" "(" expression ")" "{" ( pattern "->" expression ";" )*
+*                     "else" "->" expression ";"? "}" ;
+*    pattern        → "-"? NUMBER | STRING | "true" | "false" | "nil" | IDENTIFIER
+*                   | "[" ( pattern ( "," pattern )* )? "]"
+*                   | "{" ( ( NUMBER | STRING ) ":" pattern ( "," ... )* )? "}" ;
 */
 
+/// How deeply expressions may nest: parentheses, brackets, braces, call
+/// arguments, unary operators and patterns. Operator chains like
+/// `1 + 2 + 3` don't nest and are not limited.
+// NOTE: The parser and every pass grow the stack as they need it (see
+// `with_stack`), so this is not about the stack but a sanity bound on
+// what a person would write.
+pub const MAX_DEPTH: usize = 256;
+
+fn too_deep(t: &Token) -> LoxError {
+    LoxError::new_parse(
+        t,
+        ErrorCode::TooDeeplyNested,
+        "Expression nested too deeply",
+    )
+}
+
 /*
 * NOTE: Error handling:
 * When we can't parse, we return an error, which we propagate up (?)
@@ -29,23 +66,46 @@ use crate::{
 pub fn parse_tokens(tokens: &[Token]) -> Result<Expr, LoxError> {
     let mut it = tokens.iter().peekable();
     // TODO: handle and synchronize
-    parse_expr(&mut it)
+    let expr = parse_expr(&mut it, 0)?;
+    // Anything left over would otherwise be ignored, e.g. the `e3` of `1e3`
+    expect(&mut it, TokenType::EOF, "Expected end of input")?;
+    Ok(expr)
 }
 
 // expression → equality ;
-fn parse_expr<'a, I>(it: &mut Peekable<I>) -> Result<Expr, LoxError>
+fn parse_expr<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    parse_coalesce(it, depth)
+}
+
+// coalesce → equality ( "??" equality )* ;
+fn parse_coalesce<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
 where
     I: Iterator<Item = &'a Token>,
 {
-    parse_equality(it)
+    let mut left = parse_equality(it, depth)?;
+    while let Some(TokenType::QuestionQuestion) = it.peek().map(|t| &t.token_type) {
+        let token = it.next().expect("we just checked above");
+        left = Expr::new(
+            ExprKind::Binary(
+                Box::new(left),
+                Box::new(parse_equality(it, depth)?),
+                BinOp::QuestionQuestion,
+            ),
+            token.clone(),
+        );
+    }
+    Ok(left)
 }
 
 // equality → comparison ( ( "!=" | "==" ) comparison )* ;
-fn parse_equality<'a, I>(it: &mut Peekable<I>) -> Result<Expr, LoxError>
+fn parse_equality<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
 where
     I: Iterator<Item = &'a Token>,
 {
-    let mut left = parse_comparison(it)?;
+    let mut left = parse_comparison(it, depth)?;
     loop {
         let op = match it.peek().map(|t| &t.token_type) {
             Some(TokenType::EqualEqual) => BinOp::EqualEqual,
@@ -54,19 +114,19 @@ where
         };
         let token = it.next().expect("we just checked above");
         left = Expr::new(
-            ExprKind::Binary(Box::new(left), Box::new(parse_comparison(it)?), op),
+            ExprKind::Binary(Box::new(left), Box::new(parse_comparison(it, depth)?), op),
             token.clone(),
         );
     }
     Ok(left)
 }
 
-// comparison → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
-fn parse_comparison<'a, I>(it: &mut Peekable<I>) -> Result<Expr, LoxError>
+// comparison → range ( ( ">" | ">=" | "<" | "<=" ) range )* ;
+fn parse_comparison<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
 where
     I: Iterator<Item = &'a Token>,
 {
-    let mut left = parse_term(it)?;
+    let mut left = parse_range(it, depth)?;
     loop {
         let op = match it.peek().map(|t| &t.token_type) {
             Some(TokenType::Greater) => BinOp::Greater,
@@ -75,10 +135,111 @@ where
             Some(TokenType::LessEqual) => BinOp::LessEqual,
             _ => break,
         };
-        it.next();
         let token = it.next().expect("we just checked above");
         left = Expr::new(
-            ExprKind::Binary(Box::new(left), Box::new(parse_comparison(it)?), op),
+            ExprKind::Binary(Box::new(left), Box::new(parse_range(it, depth)?), op),
+            token.clone(),
+        );
+    }
+    Ok(left)
+}
+
+// range → bit_or ( ( ".." | "..=" ) bit_or )* ;
+fn parse_range<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let mut left = parse_bit_or(it, depth)?;
+    loop {
+        let op = match it.peek().map(|t| &t.token_type) {
+            Some(TokenType::DotDot) => BinOp::DotDot,
+            Some(TokenType::DotDotEqual) => BinOp::DotDotEqual,
+            _ => break,
+        };
+        let token = it.next().expect("we just checked above");
+        left = Expr::new(
+            ExprKind::Binary(Box::new(left), Box::new(parse_bit_or(it, depth)?), op),
+            token.clone(),
+        );
+    }
+    Ok(left)
+}
+
+// bit_or → bit_xor ( "|" bit_xor )* ;
+fn parse_bit_or<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let mut left = parse_bit_xor(it, depth)?;
+    while let Some(TokenType::Pipe) = it.peek().map(|t| &t.token_type) {
+        let token = it.next().expect("we just checked above");
+        left = Expr::new(
+            ExprKind::Binary(
+                Box::new(left),
+                Box::new(parse_bit_xor(it, depth)?),
+                BinOp::Pipe,
+            ),
+            token.clone(),
+        );
+    }
+    Ok(left)
+}
+
+// bit_xor → bit_and ( "^" bit_and )* ;
+fn parse_bit_xor<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let mut left = parse_bit_and(it, depth)?;
+    while let Some(TokenType::Caret) = it.peek().map(|t| &t.token_type) {
+        let token = it.next().expect("we just checked above");
+        left = Expr::new(
+            ExprKind::Binary(
+                Box::new(left),
+                Box::new(parse_bit_and(it, depth)?),
+                BinOp::Caret,
+            ),
+            token.clone(),
+        );
+    }
+    Ok(left)
+}
+
+// bit_and → shift ( "&" shift )* ;
+fn parse_bit_and<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let mut left = parse_shift(it, depth)?;
+    while let Some(TokenType::Ampersand) = it.peek().map(|t| &t.token_type) {
+        let token = it.next().expect("we just checked above");
+        left = Expr::new(
+            ExprKind::Binary(
+                Box::new(left),
+                Box::new(parse_shift(it, depth)?),
+                BinOp::Ampersand,
+            ),
+            token.clone(),
+        );
+    }
+    Ok(left)
+}
+
+// shift → term ( ( "<<" | ">>" ) term )* ;
+fn parse_shift<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let mut left = parse_term(it, depth)?;
+    loop {
+        let op = match it.peek().map(|t| &t.token_type) {
+            Some(TokenType::LessLess) => BinOp::LessLess,
+            Some(TokenType::GreaterGreater) => BinOp::GreaterGreater,
+            _ => break,
+        };
+        let token = it.next().expect("we just checked above");
+        left = Expr::new(
+            ExprKind::Binary(Box::new(left), Box::new(parse_term(it, depth)?), op),
             token.clone(),
         );
     }
@@ -86,11 +247,11 @@ where
 }
 
 // term → factor ( ( "-" | "+" ) factor )* ;
-fn parse_term<'a, I>(it: &mut Peekable<I>) -> Result<Expr, LoxError>
+fn parse_term<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
 where
     I: Iterator<Item = &'a Token>,
 {
-    let mut left = parse_factor(it)?;
+    let mut left = parse_factor(it, depth)?;
     loop {
         let op = match it.peek().map(|t| &t.token_type) {
             Some(TokenType::Minus) => BinOp::Minus,
@@ -99,60 +260,209 @@ where
         };
         let token = it.next().expect("we just checked above");
         left = Expr::new(
-            ExprKind::Binary(Box::new(left), Box::new(parse_factor(it)?), op),
+            ExprKind::Binary(Box::new(left), Box::new(parse_factor(it, depth)?), op),
             token.clone(),
         );
     }
     Ok(left)
 }
 
-// factor → unary ( ( "/" | "*" ) unary )* ;
-fn parse_factor<'a, I>(it: &mut Peekable<I>) -> Result<Expr, LoxError>
+// factor → unary ( ( "/" | "*" | "%" ) unary )* ;
+fn parse_factor<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
 where
     I: Iterator<Item = &'a Token>,
 {
-    let mut left = parse_unary(it)?;
+    let mut left = parse_unary(it, depth)?;
     loop {
         let op = match it.peek().map(|t| &t.token_type) {
             Some(TokenType::Slash) => BinOp::Slash,
             Some(TokenType::Star) => BinOp::Star,
+            Some(TokenType::Percent) => BinOp::Percent,
             _ => break,
         };
         let token = it.next().expect("we just checked above");
         left = Expr::new(
-            ExprKind::Binary(Box::new(left), Box::new(parse_unary(it)?), op),
+            ExprKind::Binary(Box::new(left), Box::new(parse_unary(it, depth)?), op),
             token.clone(),
         );
     }
     Ok(left)
 }
 
-// unary → ( "!" | "-" ) unary | primary ;
-fn parse_unary<'a, I>(it: &mut Peekable<I>) -> Result<Expr, LoxError>
+// unary → ( "!" | "-" | "~" ) unary | power ;
+fn parse_unary<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
 where
     I: Iterator<Item = &'a Token>,
 {
-    Ok(match it.peek().map(|t| &t.token_type) {
-        Some(TokenType::Bang) => {
-            let token = it.next().expect("we just checked above");
-            Expr::new(
-                ExprKind::Unary(Box::new(parse_unary(it)?), UnOp::Bang),
-                token.clone(),
-            )
+    if depth > MAX_DEPTH {
+        let t = it
+            .peek()
+            .expect("There should always be a final EOF token.");
+        return Err(too_deep(t));
+    }
+    // Every kind of nesting comes through here
+    with_stack(|| {
+        Ok(match it.peek().map(|t| &t.token_type) {
+            Some(TokenType::Bang) => {
+                let token = it.next().expect("we just checked above");
+                Expr::new(
+                    ExprKind::Unary(Box::new(parse_unary(it, depth + 1)?), UnOp::Bang),
+                    token.clone(),
+                )
+            }
+            Some(TokenType::Minus) => {
+                let token = it.next().expect("we just checked above");
+                Expr::new(
+                    ExprKind::Unary(Box::new(parse_unary(it, depth + 1)?), UnOp::Minus),
+                    token.clone(),
+                )
+            }
+            Some(TokenType::Tilde) => {
+                let token = it.next().expect("we just checked above");
+                Expr::new(
+                    ExprKind::Unary(Box::new(parse_unary(it, depth + 1)?), UnOp::Tilde),
+                    token.clone(),
+                )
+            }
+            _ => parse_power(it, depth)?,
+        })
+    })
+}
+
+// power → call ( "**" unary )? ;
+// Binds tighter than a unary operator on its left, so -2 ** 2 is -4, and
+// groups to the right, so 2 ** 3 ** 2 is 2 ** 9.
+fn parse_power<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let left = parse_call(it, depth)?;
+    let Some(TokenType::StarStar) = it.peek().map(|t| t.token_type) else {
+        return Ok(left);
+    };
+    let token = it.next().expect("we just checked above");
+    let right = parse_unary(it, depth + 1)?;
+    Ok(Expr::new(
+        ExprKind::Binary(Box::new(left), Box::new(right), BinOp::StarStar),
+        token.clone(),
+    ))
+}
+
+// call → primary ( "[" subscript "]" | "(" arguments? ")" )* ;
+fn parse_call<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let mut left = parse_primary(it, depth)?;
+    loop {
+        left = match it.peek().map(|t| t.token_type) {
+            Some(TokenType::LeftBracket) => {
+                let token = it.next().expect("we just checked above");
+                parse_subscript(it, left, token, depth)?
+            }
+            Some(TokenType::LeftParen) => {
+                let token = it.next().expect("we just checked above");
+                parse_arguments(it, left, token, depth)?
+            }
+            _ => return Ok(left),
+        };
+    }
+}
+
+// arguments → argument ( "," argument )* ;
+fn parse_arguments<'a, I>(
+    it: &mut Peekable<I>,
+    callee: Expr,
+    open: &Token,
+    depth: usize,
+) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let mut args = vec![];
+    if it.peek().map(|t| t.token_type) != Some(TokenType::RightParen) {
+        args.push(parse_argument(it, depth + 1)?);
+        while let Some(TokenType::Comma) = it.peek().map(|t| t.token_type) {
+            it.next();
+            args.push(parse_argument(it, depth + 1)?);
         }
-        Some(TokenType::Minus) => {
-            let token = it.next().expect("we just checked above");
-            Expr::new(
-                ExprKind::Unary(Box::new(parse_unary(it)?), UnOp::Minus),
-                token.clone(),
-            )
+    }
+    let Some(TokenType::RightParen) = it.peek().map(|t| t.token_type) else {
+        return Err(LoxError::new_parse(
+            open,
+            ErrorCode::UnclosedGroup,
+            "Expected ) after arguments",
+        ));
+    };
+    let close = it.next().expect("we just checked");
+    let mut call = Expr::new(ExprKind::Call(Box::new(callee), args), open.clone());
+    call.span = call.span.cover(close.span);
+    Ok(call)
+}
+
+// argument → "..."? expression ;
+fn parse_argument<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let Some(TokenType::DotDotDot) = it.peek().map(|t| t.token_type) else {
+        return parse_expr(it, depth);
+    };
+    let token = it.next().expect("we just checked");
+    let inner = parse_expr(it, depth + 1)?;
+    Ok(Expr::new(ExprKind::Spread(Box::new(inner)), token.clone()))
+}
+
+// subscript → expression | expression? ":" expression? ;
+fn parse_subscript<'a, I>(
+    it: &mut Peekable<I>,
+    target: Expr,
+    open: &Token,
+    depth: usize,
+) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let at = |it: &mut Peekable<I>, tt| it.peek().map(|t| t.token_type) == Some(tt);
+    let start = match at(it, TokenType::Colon) {
+        true => None,
+        false => Some(Box::new(parse_expr(it, depth + 1)?)),
+    };
+    let kind = match (start, at(it, TokenType::Colon)) {
+        (Some(index), false) => ExprKind::Index(Box::new(target), index),
+        (start, _) => {
+            it.next();
+            let end = match at(it, TokenType::RightBracket) {
+                true => None,
+                false => Some(Box::new(parse_expr(it, depth + 1)?)),
+            };
+            ExprKind::Slice(Box::new(target), start, end)
         }
-        _ => parse_primary(it)?,
-    })
+    };
+    let close = expect_bracket(it, open)?;
+    let mut expr = Expr::new(kind, open.clone());
+    expr.span = expr.span.cover(close.span);
+    Ok(expr)
 }
 
-// primary → NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" ;
-fn parse_primary<'a, I>(it: &mut Peekable<I>) -> Result<Expr, LoxError>
+fn expect_bracket<'a, I>(it: &mut Peekable<I>, open: &Token) -> Result<&'a Token, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    match it.peek().map(|t| t.token_type) {
+        Some(TokenType::RightBracket) => Ok(it.next().expect("we just checked")),
+        _ => Err(LoxError::new_parse(
+            open,
+            ErrorCode::UnclosedBracket,
+            "Expected closing ]",
+        )),
+    }
+}
+
+// primary → NUMBER | STRING | "true" | "false" | "nil" | IDENTIFIER | "(" expression ")"
+//         | "[" ( expression ( "," expression )* )? "]"
+//         | "{" ( entry ( "," entry )* )? "}" ;
+fn parse_primary<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<Expr, LoxError>
 where
     I: Iterator<Item = &'a Token>,
 {
@@ -166,18 +476,378 @@ where
         TokenType::Number => LitKind::try_from(t.literal.clone()).expect("Token literal mismatch"),
         TokenType::String => LitKind::try_from(t.literal.clone()).expect("Token literal mismatch"),
         TokenType::LeftParen => {
-            let expr = parse_expr(it)?;
+            let expr = parse_expr(it, depth + 1)?;
             if let Some(TokenType::RightParen) = it.peek().map(|t| t.token_type) {
                 let token = it.next().expect("we just checked");
-                return Ok(Expr::new(ExprKind::Grouping(Box::new(expr)), token.clone()));
+                let mut group = Expr::new(ExprKind::Grouping(Box::new(expr)), token.clone());
+                group.span = group.span.cover(t.span);
+                return Ok(group);
             }
-            let err = GenericError::new(t, "Expected closing )");
-            return Err(LoxError::ParseError(err));
+            return Err(LoxError::new_parse(
+                t,
+                ErrorCode::UnclosedGroup,
+                "Expected closing )",
+            ));
+        }
+        TokenType::Identifier => {
+            return Ok(Expr::new(ExprKind::Variable(t.lexeme.clone()), t.clone()))
         }
-        TokenType::EOF | _ => {
-            let err = GenericError::new(t, "Expected closing )");
-            return Err(LoxError::ParseError(err));
+        TokenType::LeftBracket => return parse_list(it, t, depth),
+        TokenType::LeftBrace => return parse_map(it, t, depth),
+        TokenType::Match => return parse_match(it, t, depth),
+        _ => {
+            return Err(LoxError::new_parse(
+                t,
+                ErrorCode::ExpectedExpression,
+                "Expected expression",
+            ))
         }
     };
     Ok(Expr::new(ExprKind::Literal(kind), t.clone()))
 }
+
+// NOTE: Lists, maps and matches are parsed outside of parse_primary to
+// keep its frame small, it is on the stack once per nesting level.
+
+// "[" ( expression ( "," expression )* )? "]"
+fn parse_list<'a, I>(it: &mut Peekable<I>, open: &Token, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let mut items = vec![];
+    if it.peek().map(|t| t.token_type) != Some(TokenType::RightBracket) {
+        items.push(parse_expr(it, depth + 1)?);
+        while let Some(TokenType::Comma) = it.peek().map(|t| t.token_type) {
+            it.next();
+            items.push(parse_expr(it, depth + 1)?);
+        }
+    }
+    let close = expect_bracket(it, open)?;
+    let mut list = Expr::new(ExprKind::List(items), open.clone());
+    list.span = list.span.cover(close.span);
+    Ok(list)
+}
+
+// "{" ( entry ( "," entry )* )? "}"
+fn parse_map<'a, I>(it: &mut Peekable<I>, open: &Token, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let mut entries = vec![];
+    if it.peek().map(|t| t.token_type) != Some(TokenType::RightBrace) {
+        entries.push(parse_entry(it, depth + 1)?);
+        while let Some(TokenType::Comma) = it.peek().map(|t| t.token_type) {
+            it.next();
+            entries.push(parse_entry(it, depth + 1)?);
+        }
+    }
+    let Some(TokenType::RightBrace) = it.peek().map(|t| t.token_type) else {
+        return Err(LoxError::new_parse(
+            open,
+            ErrorCode::UnclosedBrace,
+            "Expected closing }",
+        ));
+    };
+    let close = it.next().expect("we just checked");
+    let mut map = Expr::new(ExprKind::Map(entries), open.clone());
+    map.span = map.span.cover(close.span);
+    Ok(map)
+}
+
+// entry → expression ":" expression ;
+fn parse_entry<'a, I>(it: &mut Peekable<I>, depth: usize) -> Result<(Expr, Expr), LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let key = parse_expr(it, depth)?;
+    let Some(TokenType::Colon) = it.peek().map(|t| t.token_type) else {
+        let t = it
+            .peek()
+            .expect("There should always be a final EOF token.");
+        return Err(LoxError::new_parse(
+            t,
+            ErrorCode::ExpectedColon,
+            "Expected : after map key",
+        ));
+    };
+    it.next();
+    Ok((key, parse_expr(it, depth)?))
+}
+
+/// Takes the next token if it is a `token_type`, and otherwise fails with
+/// `message` at whatever is there instead.
+fn expect<'a, I>(
+    it: &mut Peekable<I>,
+    token_type: TokenType,
+    message: &str,
+) -> Result<&'a Token, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let t = it
+        .next()
+        .expect("There should always be a final EOF token.");
+    match t.token_type == token_type {
+        true => Ok(t),
+        false => Err(LoxError::new_parse(t, ErrorCode::ExpectedToken, message)),
+    }
+}
+
+// match → "match" "(" expression ")" "{" ( pattern "->" expression ";" )*
+//         "else" "->" expression ";"? "}" ;
+fn parse_match<'a, I>(it: &mut Peekable<I>, keyword: &Token, depth: usize) -> Result<Expr, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    expect(it, TokenType::LeftParen, "Expected ( after match")?;
+    let subject = parse_expr(it, depth + 1)?;
+    expect(
+        it,
+        TokenType::RightParen,
+        "Expected ) after the matched value",
+    )?;
+    let open = expect(it, TokenType::LeftBrace, "Expected { before the arms")?;
+    let mut arms = vec![];
+    let otherwise = loop {
+        match it.peek().map(|t| t.token_type) {
+            Some(TokenType::Else) => {
+                it.next();
+                expect(it, TokenType::Arrow, "Expected -> after else")?;
+                let body = parse_expr(it, depth + 1)?;
+                if let Some(TokenType::Semicolon) = it.peek().map(|t| t.token_type) {
+                    it.next();
+                }
+                break body;
+            }
+            Some(TokenType::RightBrace | TokenType::EOF) => {
+                return Err(LoxError::ParseError(
+                    GenericError::new(keyword, ErrorCode::MissingElseArm, "match has no else arm")
+                        .with_note(
+                            "the last arm has to be `else -> value`, for values no pattern matches",
+                        ),
+                ));
+            }
+            _ => (),
+        }
+        let pattern = parse_pattern(it, depth + 1, &mut vec![])?;
+        expect(it, TokenType::Arrow, "Expected -> after pattern")?;
+        let body = parse_expr(it, depth + 1)?;
+        expect(it, TokenType::Semicolon, "Expected ; after match arm")?;
+        arms.push((pattern, body));
+    };
+    let Some(TokenType::RightBrace) = it.peek().map(|t| t.token_type) else {
+        return Err(LoxError::new_parse(
+            open,
+            ErrorCode::UnclosedBrace,
+            "Expected closing } after the else arm",
+        ));
+    };
+    let close = it.next().expect("we just checked");
+    let kind = ExprKind::Match(Box::new(subject), arms, Box::new(otherwise));
+    let mut expr = Expr::new(kind, keyword.clone());
+    expr.span = expr.span.cover(close.span);
+    Ok(expr)
+}
+
+/// `bound` collects the names bound so far, so none is bound twice.
+fn parse_pattern<'a, I>(
+    it: &mut Peekable<I>,
+    depth: usize,
+    bound: &mut Vec<String>,
+) -> Result<Pattern, LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let t = it
+        .next()
+        .expect("There should always be a final EOF token.");
+    if depth > MAX_DEPTH {
+        return Err(too_deep(t));
+    }
+    let invalid =
+        |t: &Token| LoxError::new_parse(t, ErrorCode::InvalidPattern, "Expected a pattern");
+    let literal = |t: &Token| LitKind::try_from(t.literal.clone()).expect("Token literal mismatch");
+    Ok(match t.token_type {
+        TokenType::True => Pattern::Literal(LitKind::Boolean(true)),
+        TokenType::False => Pattern::Literal(LitKind::Boolean(false)),
+        TokenType::Nil => Pattern::Literal(LitKind::Nil),
+        TokenType::Number | TokenType::String => Pattern::Literal(literal(t)),
+        TokenType::Minus => match it.next() {
+            Some(n) if n.token_type == TokenType::Number => match literal(n) {
+                LitKind::Number(n) => Pattern::Literal(LitKind::Number(-n)),
+                _ => unreachable!("number tokens hold numbers"),
+            },
+            _ => return Err(invalid(t)),
+        },
+        TokenType::Identifier if t.lexeme == "_" => Pattern::Binding(t.lexeme.clone()),
+        TokenType::Identifier if bound.contains(&t.lexeme) => {
+            return Err(LoxError::new_parse(
+                t,
+                ErrorCode::DuplicateBinding,
+                &format!("{} is bound twice in this pattern", t.lexeme),
+            ))
+        }
+        TokenType::Identifier => {
+            bound.push(t.lexeme.clone());
+            Pattern::Binding(t.lexeme.clone())
+        }
+        TokenType::LeftBracket => {
+            let mut items = vec![];
+            if it.peek().map(|t| t.token_type) != Some(TokenType::RightBracket) {
+                items.push(parse_pattern(it, depth + 1, bound)?);
+                while let Some(TokenType::Comma) = it.peek().map(|t| t.token_type) {
+                    it.next();
+                    items.push(parse_pattern(it, depth + 1, bound)?);
+                }
+            }
+            expect_bracket(it, t)?;
+            Pattern::List(items)
+        }
+        TokenType::LeftBrace => {
+            let mut entries = vec![];
+            if it.peek().map(|t| t.token_type) != Some(TokenType::RightBrace) {
+                entries.push(parse_pattern_entry(it, depth + 1, bound)?);
+                while let Some(TokenType::Comma) = it.peek().map(|t| t.token_type) {
+                    it.next();
+                    entries.push(parse_pattern_entry(it, depth + 1, bound)?);
+                }
+            }
+            let Some(TokenType::RightBrace) = it.peek().map(|t| t.token_type) else {
+                return Err(LoxError::new_parse(
+                    t,
+                    ErrorCode::UnclosedBrace,
+                    "Expected closing }",
+                ));
+            };
+            it.next();
+            Pattern::Map(entries)
+        }
+        _ => return Err(invalid(t)),
+    })
+}
+
+// ( NUMBER | STRING ) ":" pattern
+fn parse_pattern_entry<'a, I>(
+    it: &mut Peekable<I>,
+    depth: usize,
+    bound: &mut Vec<String>,
+) -> Result<(LitKind, Pattern), LoxError>
+where
+    I: Iterator<Item = &'a Token>,
+{
+    let t = it
+        .next()
+        .expect("There should always be a final EOF token.");
+    let (TokenType::Number | TokenType::String) = t.token_type else {
+        return Err(LoxError::new_parse(
+            t,
+            ErrorCode::InvalidPattern,
+            "Expected a number or string key",
+        ));
+    };
+    let key = LitKind::try_from(t.literal.clone()).expect("Token literal mismatch");
+    let Some(TokenType::Colon) = it.peek().map(|t| t.token_type) else {
+        let t = it
+            .peek()
+            .expect("There should always be a final EOF token.");
+        return Err(LoxError::new_parse(
+            t,
+            ErrorCode::ExpectedColon,
+            "Expected : after map key",
+        ));
+    };
+    it.next();
+    Ok((key, parse_pattern(it, depth, bound)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpreter::Interpreter, lint::lint, scanner::scan_tokens};
+
+    fn parse(source: &str) -> Result<Expr, LoxError> {
+        parse_tokens(&scan_tokens(source).unwrap())
+    }
+
+    #[test]
+    fn test_trailing_tokens() {
+        for source in ["1 2", "1e3", "(1))", "\"a\" nil"] {
+            match parse(source) {
+                Err(LoxError::ParseError(e)) => assert_eq!(e.code, ErrorCode::ExpectedToken),
+                other => panic!("expected a parse error, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let nested = |n| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+        // NOTE: One term per line, columns are counted from the start of the line
+        let chained = |n| vec!["1"; n].join(" +\n");
+        let negated = |n| format!("{}1", "-".repeat(n));
+        let pattern = |n| {
+            format!(
+                "match (1) {{ {}{} -> 1; else -> 2 }}",
+                "[".repeat(n),
+                "]".repeat(n)
+            )
+        };
+
+        for source in [
+            nested(MAX_DEPTH - 1),
+            negated(MAX_DEPTH - 1),
+            // Chains don't nest, however long they are
+            chained(1_000),
+            chained(20_000),
+        ] {
+            let expr = parse(&source).unwrap();
+            // Every pass has to survive the tallest tree the parser lets through
+            lint(&expr);
+            Interpreter::new().interpret(&expr).unwrap();
+        }
+        for source in [nested(1_000), negated(1_000), pattern(1_000)] {
+            match parse(&source) {
+                Err(LoxError::ParseError(e)) => assert_eq!(e.code, ErrorCode::TooDeeplyNested),
+                other => panic!("expected a parse error, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_match_errors() {
+        for (source, code) in [
+            ("match 1 { else -> 1 }", ErrorCode::ExpectedToken),
+            ("match (1) { 1 -> 2 }", ErrorCode::ExpectedToken),
+            ("match (1) { 1 -> 2; }", ErrorCode::MissingElseArm),
+            ("match (1) { 1 2; else -> 3 }", ErrorCode::ExpectedToken),
+            (
+                "match (1) { 1 + 1 -> 2; else -> 3 }",
+                ErrorCode::ExpectedToken,
+            ),
+            (
+                "match (1) { (1) -> 2; else -> 3 }",
+                ErrorCode::InvalidPattern,
+            ),
+            (
+                "match (1) { {x: 1} -> 2; else -> 3 }",
+                ErrorCode::InvalidPattern,
+            ),
+            (
+                "match (1) { {\"x\" 1} -> 2; else -> 3 }",
+                ErrorCode::ExpectedColon,
+            ),
+            (
+                "match (1) { [a, [a]] -> 2; else -> 3 }",
+                ErrorCode::DuplicateBinding,
+            ),
+            ("match (1) { else -> 3; 1 -> 2 }", ErrorCode::UnclosedBrace),
+        ] {
+            match parse(source) {
+                Err(LoxError::ParseError(e)) => assert_eq!(e.code, code, "{source}"),
+                other => panic!("{source}: expected a parse error, got {other:?}"),
+            }
+        }
+        // _ can be used any number of times
+        assert!(parse("match (1) { [_, _] -> 2; else -> 3; }").is_ok());
+    }
+}
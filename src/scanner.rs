@@ -1,24 +1,43 @@
-use anyhow::{anyhow, Result};
+use std::{iter::Peekable, ops::Range, str::Chars};
+
 use derive_more::{Constructor, Display};
-use itertools::Itertools;
+use itertools::{Itertools, PeekingNext};
+use unicode_ident::{is_xid_continue, is_xid_start};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::errors::{ErrorCode, GenericError, LoxError};
 
 #[derive(Display, Debug, PartialEq, Eq, Clone, Copy)]
-#[allow(dead_code)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenType {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
 
     // One or two character tokens.
+    StarStar,
+    DotDot,
+    DotDotEqual,
+    DotDotDot,
+    QuestionQuestion,
+    Arrow,
     Bang,
     BangEqual,
     Equal,
@@ -27,6 +46,8 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
 
     // Literals.
     Identifier,
@@ -41,6 +62,7 @@ pub enum TokenType {
     Fun,
     For,
     If,
+    Match,
     Nil,
     Or,
     Print,
@@ -50,6 +72,10 @@ pub enum TokenType {
     True,
     Var,
     While,
+
+    // Trivia, only produced on request.
+    Comment,
+
     EOF,
 }
 
@@ -63,6 +89,7 @@ impl TokenType {
             "for" => Self::For,
             "fun" => Self::Fun,
             "if" => Self::If,
+            "match" => Self::Match,
             "nil" => Self::Nil,
             "or" => Self::Or,
             "print" => Self::Print,
@@ -78,13 +105,34 @@ impl TokenType {
 }
 
 #[derive(Debug, Display, PartialEq, Clone)]
-#[allow(dead_code)]
 pub enum Literal {
     Null,
     Text(String),
     Number(f32), // NOTE: it would prob be good to have multiple number types
 }
 
+/// Where a token sits in the source. `start..end` is a byte range, so it can
+/// be used to slice the source directly; `column` is 1-based and counts
+/// grapheme clusters, which is what a user sees as characters.
+#[derive(Debug, Constructor, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub column: u32,
+}
+
+impl Span {
+    /// The smallest span containing both `self` and `other`.
+    pub fn cover(self, other: Span) -> Span {
+        let first = if other.start < self.start {
+            other
+        } else {
+            self
+        };
+        Span::new(first.start, self.end.max(other.end), first.column)
+    }
+}
+
 #[derive(Debug, Display, Constructor, PartialEq, Clone)]
 #[display("{} {} {:?}", token_type, lexeme, literal)]
 pub struct Token {
@@ -92,96 +140,269 @@ pub struct Token {
     pub lexeme: String,
     pub literal: Literal,
     pub line: u32,
+    pub span: Span,
 }
 
 impl Token {
     pub fn new_simple(token_type: TokenType, text: impl ToString, line: u32) -> Self {
-        Self::new(token_type, text.to_string(), Literal::Null, line)
+        Self::new(
+            token_type,
+            text.to_string(),
+            Literal::Null,
+            line,
+            Span::default(),
+        )
     }
 
-    pub fn new_number(text: &str, line: u32) -> Result<Self> {
-        let number: f32 = text.parse().map_err(|_| anyhow!("Invalid number."))?;
-        Ok(Self::new(
+    /// `None` if `text` is not a number.
+    pub fn new_number(text: &str, line: u32) -> Option<Self> {
+        let number: f32 = text.parse().ok()?;
+        Some(Self::new(
             TokenType::Number,
             text.to_string(),
             Literal::Number(number),
             line,
+            Span::default(),
         ))
     }
 }
 
-pub fn scan_tokens(source: &str) -> Result<Vec<Token>> {
+/// A peekable char iterator that keeps track of the byte offset it is at,
+/// so tokens can record where in the source they came from.
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(source: &'a str, offset: usize) -> Self {
+        Self {
+            chars: source[offset..].chars().peekable(),
+            offset,
+        }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    /// The character after the one `peek` returns.
+    fn peek_second(&self) -> Option<char> {
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next()
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+}
+
+impl PeekingNext for Cursor<'_> {
+    fn peeking_next<F>(&mut self, accept: F) -> Option<char>
+    where
+        F: FnOnce(&char) -> bool,
+    {
+        if !accept(self.peek()?) {
+            return None;
+        }
+        self.next()
+    }
+}
+
+fn column(source: &str, line_start: usize, offset: usize) -> u32 {
+    source[line_start..offset].graphemes(true).count() as u32 + 1
+}
+
+fn scan_error(
+    source: &str,
+    line: u32,
+    line_start: usize,
+    range: Range<usize>,
+    code: ErrorCode,
+    message: &str,
+) -> LoxError {
+    let span = Span::new(
+        range.start,
+        range.end,
+        column(source, line_start, range.start),
+    );
+    LoxError::ScanError(GenericError::at(line, &source[range], span, code, message))
+}
+
+/// Scans `source`, stopping at the first error.
+pub fn scan_tokens(source: &str) -> Result<Vec<Token>, LoxError> {
+    scan(source, false)
+}
+
+/// Same as [`scan_tokens`], but `//` comments are kept as `Comment` tokens
+/// so tooling (formatters, doc generators) can round-trip the source.
+/// The parser does not understand them, so don't feed these tokens to it.
+pub fn scan_tokens_with_comments(source: &str) -> Result<Vec<Token>, LoxError> {
+    scan(source, true)
+}
+
+/// Scans all of `source` even if it has errors, so every one of them can be
+/// reported. The tokens around an error are a best guess, e.g. an
+/// unterminated string runs to the end of its line.
+pub fn scan_tokens_recovering(source: &str) -> (Vec<Token>, Vec<LoxError>) {
+    scan_from(source, 0, 0, false, |_| false)
+}
+
+fn scan(source: &str, keep_comments: bool) -> Result<Vec<Token>, LoxError> {
+    first_error(scan_from(source, 0, 0, keep_comments, |_| false))
+}
+
+fn first_error((tokens, errors): (Vec<Token>, Vec<LoxError>)) -> Result<Vec<Token>, LoxError> {
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(tokens),
+    }
+}
+
+/// Scans `source` starting at byte `offset`, which must be on line `line`
+/// and between two tokens. If `sync` returns true for a freshly scanned
+/// token, scanning stops right there, and that token is the last one
+/// returned instead of `EOF`.
+/// Errors don't stop the scan, they are returned next to the tokens.
+fn scan_from(
+    source: &str,
+    offset: usize,
+    mut line: u32,
+    keep_comments: bool,
+    mut sync: impl FnMut(&Token) -> bool,
+) -> (Vec<Token>, Vec<LoxError>) {
     let mut tokens: Vec<Token> = vec![];
-    let mut line = 0;
+    let mut errors = vec![];
+    let mut line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
 
     type TT = TokenType;
-    let mut chrs = source.chars().peekable();
+    let mut chrs = Cursor::new(source, offset);
+
+    // Allow `#!/usr/bin/env jilox` so scripts can be made executable.
+    // The newline is left in place to keep line numbers right.
+    if offset == 0 && source.starts_with("#!") {
+        chrs.peeking_take_while(|&c| c != '\n').for_each(drop);
+    }
 
     while let Some(c) = chrs.next() {
+        let start = chrs.offset - c.len_utf8();
+        let token_line = line;
+        let token_line_start = line_start;
+        let pushed = tokens.len();
+
         match c {
             '(' => tokens.push(Token::new_simple(TT::LeftParen, c, line)),
             ')' => tokens.push(Token::new_simple(TT::RightParen, c, line)),
             '{' => tokens.push(Token::new_simple(TT::LeftBrace, c, line)),
             '}' => tokens.push(Token::new_simple(TT::RightBrace, c, line)),
+            '[' => tokens.push(Token::new_simple(TT::LeftBracket, c, line)),
+            ']' => tokens.push(Token::new_simple(TT::RightBracket, c, line)),
             ',' => tokens.push(Token::new_simple(TT::Comma, c, line)),
-            '.' => tokens.push(Token::new_simple(TT::Dot, c, line)),
+            ':' => tokens.push(Token::new_simple(TT::Colon, c, line)),
+            '.' => {
+                if let Some('.') = chrs.peek() {
+                    chrs.next();
+                    if let Some('=') = chrs.peek() {
+                        tokens.push(Token::new_simple(TT::DotDotEqual, "..=", line));
+                        chrs.next();
+                    } else if let Some('.') = chrs.peek() {
+                        tokens.push(Token::new_simple(TT::DotDotDot, "...", line));
+                        chrs.next();
+                    } else {
+                        tokens.push(Token::new_simple(TT::DotDot, "..", line));
+                    }
+                } else {
+                    tokens.push(Token::new_simple(TT::Dot, c, line));
+                }
+            }
+            '-' if chrs.peek() == Some(&'>') => {
+                chrs.next();
+                tokens.push(Token::new_simple(TT::Arrow, "->", line));
+            }
             '-' => tokens.push(Token::new_simple(TT::Minus, c, line)),
             '+' => tokens.push(Token::new_simple(TT::Plus, c, line)),
             ';' => tokens.push(Token::new_simple(TT::Semicolon, c, line)),
-            '*' => tokens.push(Token::new_simple(TT::Star, c, line)),
+            '*' => {
+                if let Some('*') = chrs.peek() {
+                    tokens.push(Token::new_simple(TT::StarStar, "**", line));
+                    chrs.next();
+                } else {
+                    tokens.push(Token::new_simple(TT::Star, c, line));
+                }
+            }
+            '%' => tokens.push(Token::new_simple(TT::Percent, c, line)),
+            '&' => tokens.push(Token::new_simple(TT::Ampersand, c, line)),
+            '|' => tokens.push(Token::new_simple(TT::Pipe, c, line)),
+            '^' => tokens.push(Token::new_simple(TT::Caret, c, line)),
+            '~' => tokens.push(Token::new_simple(TT::Tilde, c, line)),
+            '?' if chrs.peek() == Some(&'?') => {
+                chrs.next();
+                tokens.push(Token::new_simple(TT::QuestionQuestion, "??", line));
+            }
             '!' => {
-                if let Some(&c1) = chrs.peek() {
-                    if c1 == '=' {
-                        tokens.push(Token::new_simple(TT::BangEqual, "!=", line));
-                        chrs.next();
-                    } else {
-                        tokens.push(Token::new_simple(TT::Bang, "!", line));
-                    }
+                if let Some('=') = chrs.peek() {
+                    tokens.push(Token::new_simple(TT::BangEqual, "!=", line));
+                    chrs.next();
+                } else {
+                    tokens.push(Token::new_simple(TT::Bang, "!", line));
                 }
             }
             '=' => {
-                if let Some(&c1) = chrs.peek() {
-                    if c1 == '=' {
-                        tokens.push(Token::new_simple(TT::EqualEqual, "==", line));
-                        chrs.next();
-                    } else {
-                        tokens.push(Token::new_simple(TT::Equal, c, line));
-                    }
+                if let Some('=') = chrs.peek() {
+                    tokens.push(Token::new_simple(TT::EqualEqual, "==", line));
+                    chrs.next();
+                } else {
+                    tokens.push(Token::new_simple(TT::Equal, c, line));
                 }
             }
             '<' => {
-                if let Some(&c1) = chrs.peek() {
-                    if c1 == '=' {
-                        tokens.push(Token::new_simple(TT::LessEqual, "<=", line));
-                        chrs.next();
-                    } else {
-                        tokens.push(Token::new_simple(TT::Less, c, line));
-                    }
+                if let Some('=') = chrs.peek() {
+                    tokens.push(Token::new_simple(TT::LessEqual, "<=", line));
+                    chrs.next();
+                } else if let Some('<') = chrs.peek() {
+                    tokens.push(Token::new_simple(TT::LessLess, "<<", line));
+                    chrs.next();
+                } else {
+                    tokens.push(Token::new_simple(TT::Less, c, line));
                 }
             }
             '>' => {
-                if let Some(&c1) = chrs.peek() {
-                    if c1 == '=' {
-                        tokens.push(Token::new_simple(TT::GreaterEqual, ">=", line));
-                        chrs.next();
-                    } else {
-                        tokens.push(Token::new_simple(TT::Greater, c, line));
-                    }
+                if let Some('=') = chrs.peek() {
+                    tokens.push(Token::new_simple(TT::GreaterEqual, ">=", line));
+                    chrs.next();
+                } else if let Some('>') = chrs.peek() {
+                    tokens.push(Token::new_simple(TT::GreaterGreater, ">>", line));
+                    chrs.next();
+                } else {
+                    tokens.push(Token::new_simple(TT::Greater, c, line));
                 }
             }
             '/' => {
-                if let Some(&c1) = chrs.peek() {
-                    if c1 == '/' {
-                        let _ = chrs.by_ref().take_while(|&c| c != '\n');
-                    } else {
-                        tokens.push(Token::new_simple(TT::Slash, '/', line));
+                if let Some('/') = chrs.peek() {
+                    let comment: String = std::iter::once(c)
+                        .chain(chrs.by_ref().peeking_take_while(|&c| c != '\n'))
+                        .collect();
+                    if keep_comments {
+                        tokens.push(Token::new_simple(TT::Comment, comment, line));
                     }
+                } else {
+                    tokens.push(Token::new_simple(TT::Slash, c, line));
                 }
             }
             ' ' => continue,
             '\r' => continue,
             '\t' => continue,
-            '\n' => line += 1,
+            '\n' => {
+                line += 1;
+                line_start = chrs.offset;
+            }
             '"' => {
                 let literal: String = chrs
                     .by_ref()
@@ -193,61 +414,115 @@ pub fn scan_tokens(source: &str) -> Result<Vec<Token>> {
                     })
                     .collect();
 
-                if let None = chrs.next() {
-                    return Err(anyhow!("Unterminated string."));
-                }
+                if chrs.next().is_none() {
+                    errors.push(scan_error(
+                        source,
+                        token_line,
+                        token_line_start,
+                        start..start + 1,
+                        ErrorCode::UnterminatedString,
+                        "Unterminated string.",
+                    ));
+                    // Guess that the string was meant to end with its line,
+                    // and carry on scanning from there.
+                    let eol = source[start..]
+                        .find('\n')
+                        .map_or(source.len(), |i| start + i);
+                    let literal = source[start + 1..eol].trim_end_matches('\r');
+                    tokens.push(Token::new(
+                        TT::String,
+                        source[start..eol].to_string(),
+                        Literal::Text(literal.to_string()),
+                        token_line,
+                        Span::default(),
+                    ));
+                    chrs = Cursor::new(source, eol);
+                    line = token_line;
+                    line_start = token_line_start;
+                } else {
+                    if let Some(i) = literal.rfind('\n') {
+                        // skip the opening quote and the newline itself
+                        line_start = start + i + 2;
+                    }
 
-                let lexeme = format!("\"{}\"", literal);
+                    let lexeme = format!("\"{}\"", literal);
 
-                tokens.push(Token::new(TT::String, lexeme, Literal::Text(literal), line));
+                    tokens.push(Token::new(
+                        TT::String,
+                        lexeme,
+                        Literal::Text(literal),
+                        line,
+                        Span::default(),
+                    ));
+                }
             }
             _ => {
-                if c.is_digit(10) {
-                    let decimal: String = std::iter::once(c)
+                if c.is_ascii_digit() {
+                    let mut text: String = std::iter::once(c)
                         .chain(
                             chrs.by_ref()
-                                .peeking_take_while(|&c| c != '.' && c.is_digit(10)),
+                                .peeking_take_while(|&c| c != '.' && c.is_ascii_digit()),
                         )
                         .collect();
-                    match chrs.peek() {
-                        None => {
-                            tokens.push(Token::new_number(&decimal, line)?);
-                            continue;
-                        }
-                        Some(&c) => {
-                            if c != '.' {
-                                tokens.push(Token::new_number(&decimal, line)?);
-                                continue;
-                            }
-                            chrs.next();
-                            let fractional: String = chrs
-                                .by_ref()
-                                .peeking_take_while(|&c| c.is_digit(10))
-                                .collect();
-                            if fractional.len() == 0 {
-                                return Err(anyhow!(
-                                    "Invalid number: {}. is not a valid number",
-                                    decimal
-                                ));
-                            }
-                            let text = format!("{}.{}", decimal, fractional);
-                            tokens.push(Token::new_number(&text, line)?);
+                    // `1..2` is a range, not the number `1.` followed by `.2`
+                    if chrs.peek() == Some(&'.') && chrs.peek_second() != Some('.') {
+                        chrs.next();
+                        let fractional: String = chrs
+                            .by_ref()
+                            .peeking_take_while(|&c| c.is_ascii_digit())
+                            .collect();
+                        if fractional.is_empty() {
+                            errors.push(scan_error(
+                                source,
+                                line,
+                                token_line_start,
+                                start..chrs.offset,
+                                ErrorCode::InvalidNumber,
+                                &format!("Invalid number: {}. is not a valid number", text),
+                            ));
+                        } else {
+                            text = format!("{}.{}", text, fractional);
                         }
                     }
-                } else if c.is_alphabetic() || c == '_' {
+                    match Token::new_number(&text, line) {
+                        Some(token) => tokens.push(token),
+                        None => errors.push(scan_error(
+                            source,
+                            line,
+                            token_line_start,
+                            start..chrs.offset,
+                            ErrorCode::InvalidNumber,
+                            "Invalid number.",
+                        )),
+                    }
+                } else if is_xid_start(c) || c == '_' {
+                    // Identifiers follow UAX#31: XID_Start (or '_') then XID_Continue.
                     let keyword: String = std::iter::once(c)
-                        .chain(
-                            chrs.by_ref()
-                                .peeking_take_while(|&c| c.is_alphanumeric() || c == '_'),
-                        )
+                        .chain(chrs.by_ref().peeking_take_while(|&c| is_xid_continue(c)))
                         .collect();
                     let token_type = TokenType::from_keyword(&keyword);
                     tokens.push(Token::new_simple(token_type, keyword, line));
                 } else {
-                    return Err(anyhow!("Unexpected character."));
+                    errors.push(scan_error(
+                        source,
+                        line,
+                        token_line_start,
+                        start..chrs.offset,
+                        ErrorCode::UnexpectedCharacter,
+                        "Unexpected character.",
+                    ));
                 }
             }
         }
+
+        if tokens.len() > pushed {
+            let column = column(source, token_line_start, start);
+            let token = tokens.last_mut().expect("we just checked above");
+            token.span = Span::new(start, chrs.offset, column);
+            if sync(token) {
+                return (tokens, errors);
+            }
+        }
     }
 
     tokens.push(Token::new(
@@ -255,8 +530,97 @@ pub fn scan_tokens(source: &str) -> Result<Vec<Token>> {
         "".to_string(),
         Literal::Null,
         line,
+        Span::new(
+            source.len(),
+            source.len(),
+            column(source, line_start, source.len()),
+        ),
     ));
 
+    (tokens, errors)
+}
+
+/// A single text edit: the bytes `range` of the old source were replaced
+/// with `text`.
+#[derive(Debug, Constructor, Clone)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn apply(&self, source: &str) -> String {
+        let mut edited = source.to_string();
+        edited.replace_range(self.range.clone(), &self.text);
+        edited
+    }
+}
+
+/*
+* NOTE: Incremental re-scanning. Tokens that end before the edit are kept
+* as they are. We restart the scanner after the last of them, and stop as
+* soon as it produces a token past the edit that starts exactly where an
+* old token started (after shifting by the edit's length). From there on
+* the source is the same as before, and the scanner keeps no state
+* between tokens, so the rest of the old tokens can be reused. They only
+* need their offsets and lines shifted. Columns are also recomputed on
+* the line where we resynced.
+*/
+
+/// Produces the same tokens as `scan_tokens(new_source)`, reusing
+/// `old_tokens` (from `scan_tokens` on the source before `edit`) wherever
+/// the edit can't have changed them.
+pub fn rescan(old_tokens: &[Token], edit: &Edit, new_source: &str) -> Result<Vec<Token>, LoxError> {
+    let kept = old_tokens
+        .iter()
+        .take_while(|t| t.token_type != TokenType::EOF && t.span.end < edit.range.start)
+        .count();
+    let (offset, line) = match kept {
+        0 => (0, 0),
+        n => (old_tokens[n - 1].span.end, old_tokens[n - 1].line),
+    };
+
+    let edit_end = edit.range.start + edit.text.len();
+    let delta = edit_end as isize - edit.range.end as isize;
+    let mut resync = None;
+    let mut fresh = first_error(scan_from(new_source, offset, line, false, |t| {
+        if t.span.start < edit_end {
+            return false;
+        }
+        let old_start = (t.span.start as isize - delta) as usize;
+        resync = old_tokens[kept..]
+            .binary_search_by_key(&old_start, |old| old.span.start)
+            .ok()
+            .map(|i| kept + i)
+            .filter(|&i| old_tokens[i].lexeme == t.lexeme);
+        resync.is_some()
+    }))?;
+
+    let mut tokens = old_tokens[..kept].to_vec();
+    let Some(i) = resync else {
+        tokens.append(&mut fresh);
+        return Ok(tokens);
+    };
+
+    let synced = fresh.pop().expect("scanning stopped on this token");
+    tokens.append(&mut fresh);
+    let line_delta = synced.line as i64 - old_tokens[i].line as i64;
+    let line_start = new_source[..synced.span.start]
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    let line_end = new_source[synced.span.start..]
+        .find('\n')
+        .map_or(new_source.len(), |i| synced.span.start + i);
+    for old in &old_tokens[i..] {
+        let mut token = old.clone();
+        token.span.start = (token.span.start as isize + delta) as usize;
+        token.span.end = (token.span.end as isize + delta) as usize;
+        token.line = (token.line as i64 + line_delta) as u32;
+        if token.span.start <= line_end {
+            token.span.column = column(new_source, line_start, token.span.start);
+        }
+        tokens.push(token);
+    }
     Ok(tokens)
 }
 
@@ -273,6 +637,7 @@ mod tests {
             String::from("\"abc\""),
             Literal::Text(String::from("abc")),
             0,
+            Span::new(1, 6, 2),
         );
         assert_eq!(tokens.len(), 2);
         assert_eq!(tokens[0], token);
@@ -282,21 +647,100 @@ mod tests {
     fn test_misc_tokens() {
         let input = "! != = == () \n <=<.";
         let want: Vec<Token> = vec![
-            Token::new(TokenType::Bang, String::from("!"), Literal::Null, 0),
-            Token::new(TokenType::BangEqual, String::from("!="), Literal::Null, 0),
-            Token::new(TokenType::Equal, String::from("="), Literal::Null, 0),
-            Token::new(TokenType::EqualEqual, String::from("=="), Literal::Null, 0),
-            Token::new(TokenType::LeftParen, String::from("("), Literal::Null, 0),
-            Token::new(TokenType::RightParen, String::from(")"), Literal::Null, 0),
-            Token::new(TokenType::LessEqual, String::from("<="), Literal::Null, 1),
-            Token::new(TokenType::Less, String::from("<"), Literal::Null, 1),
-            Token::new(TokenType::Dot, String::from("."), Literal::Null, 1),
-            Token::new(TokenType::EOF, "".to_string(), Literal::Null, 1),
+            Token::new(
+                TokenType::Bang,
+                String::from("!"),
+                Literal::Null,
+                0,
+                Span::new(0, 1, 1),
+            ),
+            Token::new(
+                TokenType::BangEqual,
+                String::from("!="),
+                Literal::Null,
+                0,
+                Span::new(2, 4, 3),
+            ),
+            Token::new(
+                TokenType::Equal,
+                String::from("="),
+                Literal::Null,
+                0,
+                Span::new(5, 6, 6),
+            ),
+            Token::new(
+                TokenType::EqualEqual,
+                String::from("=="),
+                Literal::Null,
+                0,
+                Span::new(7, 9, 8),
+            ),
+            Token::new(
+                TokenType::LeftParen,
+                String::from("("),
+                Literal::Null,
+                0,
+                Span::new(10, 11, 11),
+            ),
+            Token::new(
+                TokenType::RightParen,
+                String::from(")"),
+                Literal::Null,
+                0,
+                Span::new(11, 12, 12),
+            ),
+            Token::new(
+                TokenType::LessEqual,
+                String::from("<="),
+                Literal::Null,
+                1,
+                Span::new(15, 17, 2),
+            ),
+            Token::new(
+                TokenType::Less,
+                String::from("<"),
+                Literal::Null,
+                1,
+                Span::new(17, 18, 4),
+            ),
+            Token::new(
+                TokenType::Dot,
+                String::from("."),
+                Literal::Null,
+                1,
+                Span::new(18, 19, 5),
+            ),
+            Token::new(
+                TokenType::EOF,
+                "".to_string(),
+                Literal::Null,
+                1,
+                Span::new(19, 19, 6),
+            ),
         ];
         let tokens = scan_tokens(input).unwrap();
         assert_eq!(want, tokens);
     }
 
+    #[test]
+    fn test_operator_at_end() {
+        for (source, want) in [
+            ("1 !", TokenType::Bang),
+            ("1 =", TokenType::Equal),
+            ("1 <", TokenType::Less),
+            ("1 >", TokenType::Greater),
+            ("1 *", TokenType::Star),
+            ("1 .", TokenType::Dot),
+        ] {
+            let types: Vec<_> = scan_tokens(source)
+                .unwrap()
+                .into_iter()
+                .map(|t| t.token_type)
+                .collect();
+            assert_eq!(types, [TokenType::Number, want, TokenType::EOF], "{source}");
+        }
+    }
+
     #[test]
     fn test_number() {
         let input = "123 123.23";
@@ -306,14 +750,22 @@ mod tests {
                 "123".to_string(),
                 Literal::Number(123.),
                 0,
+                Span::new(0, 3, 1),
             ),
             Token::new(
                 TokenType::Number,
                 "123.23".to_string(),
                 Literal::Number(123.23),
                 0,
+                Span::new(4, 10, 5),
+            ),
+            Token::new(
+                TokenType::EOF,
+                "".to_string(),
+                Literal::Null,
+                0,
+                Span::new(10, 10, 11),
             ),
-            Token::new(TokenType::EOF, "".to_string(), Literal::Null, 0),
         ];
         let tokens = scan_tokens(input).unwrap();
         assert_eq!(want, tokens);
@@ -323,14 +775,254 @@ mod tests {
     fn test_identifier() {
         let input = "while if true xy_zt\n__x1";
         let want: Vec<Token> = vec![
-            Token::new(TokenType::While, "while".to_string(), Literal::Null, 0),
-            Token::new(TokenType::If, "if".to_string(), Literal::Null, 0),
-            Token::new(TokenType::True, "true".to_string(), Literal::Null, 0),
-            Token::new(TokenType::Identifier, "xy_zt".to_string(), Literal::Null, 0),
-            Token::new(TokenType::Identifier, "__x1".to_string(), Literal::Null, 1),
-            Token::new(TokenType::EOF, "".to_string(), Literal::Null, 1),
+            Token::new(
+                TokenType::While,
+                "while".to_string(),
+                Literal::Null,
+                0,
+                Span::new(0, 5, 1),
+            ),
+            Token::new(
+                TokenType::If,
+                "if".to_string(),
+                Literal::Null,
+                0,
+                Span::new(6, 8, 7),
+            ),
+            Token::new(
+                TokenType::True,
+                "true".to_string(),
+                Literal::Null,
+                0,
+                Span::new(9, 13, 10),
+            ),
+            Token::new(
+                TokenType::Identifier,
+                "xy_zt".to_string(),
+                Literal::Null,
+                0,
+                Span::new(14, 19, 15),
+            ),
+            Token::new(
+                TokenType::Identifier,
+                "__x1".to_string(),
+                Literal::Null,
+                1,
+                Span::new(20, 24, 1),
+            ),
+            Token::new(
+                TokenType::EOF,
+                "".to_string(),
+                Literal::Null,
+                1,
+                Span::new(24, 24, 5),
+            ),
+        ];
+        let tokens = scan_tokens(input).unwrap();
+        assert_eq!(want, tokens);
+    }
+
+    #[test]
+    fn test_unicode_identifier() {
+        let input = "变量 == café\n\"多\n行\" ";
+        let want: Vec<Token> = vec![
+            Token::new(
+                TokenType::Identifier,
+                "变量".to_string(),
+                Literal::Null,
+                0,
+                Span::new(0, 6, 1),
+            ),
+            Token::new(
+                TokenType::EqualEqual,
+                "==".to_string(),
+                Literal::Null,
+                0,
+                Span::new(7, 9, 4),
+            ),
+            Token::new(
+                TokenType::Identifier,
+                "café".to_string(),
+                Literal::Null,
+                0,
+                Span::new(10, 15, 7),
+            ),
+            Token::new(
+                TokenType::String,
+                "\"多\n行\"".to_string(),
+                Literal::Text("多\n行".to_string()),
+                2,
+                Span::new(16, 25, 1),
+            ),
+            Token::new(
+                TokenType::EOF,
+                "".to_string(),
+                Literal::Null,
+                2,
+                Span::new(26, 26, 4),
+            ),
         ];
         let tokens = scan_tokens(input).unwrap();
         assert_eq!(want, tokens);
+        for token in &tokens {
+            assert_eq!(&input[token.span.start..token.span.end], token.lexeme);
+        }
+    }
+
+    #[test]
+    fn test_grapheme_columns() {
+        // 'e' followed by a combining acute accent is one grapheme, two chars
+        let input = "e\u{301}x + y";
+        let tokens = scan_tokens(input).unwrap();
+        assert_eq!(tokens[0].token_type, TokenType::Identifier);
+        assert_eq!(tokens[0].span, Span::new(0, 4, 1));
+        assert_eq!(tokens[1].span, Span::new(5, 6, 4));
+        assert_eq!(tokens[2].span, Span::new(7, 8, 6));
+
+        assert!(scan_tokens("1x€").is_err());
+        assert!(scan_tokens("\u{301}x").is_err());
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "1 // one\n/ 2 //";
+        let want: Vec<Token> = vec![
+            Token::new(
+                TokenType::Number,
+                "1".to_string(),
+                Literal::Number(1.),
+                0,
+                Span::new(0, 1, 1),
+            ),
+            Token::new(
+                TokenType::Slash,
+                "/".to_string(),
+                Literal::Null,
+                1,
+                Span::new(9, 10, 1),
+            ),
+            Token::new(
+                TokenType::Number,
+                "2".to_string(),
+                Literal::Number(2.),
+                1,
+                Span::new(11, 12, 3),
+            ),
+            Token::new(
+                TokenType::EOF,
+                "".to_string(),
+                Literal::Null,
+                1,
+                Span::new(15, 15, 7),
+            ),
+        ];
+        assert_eq!(want, scan_tokens(input).unwrap());
+
+        let tokens = scan_tokens_with_comments(input).unwrap();
+        let comments: Vec<&str> = tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::Comment)
+            .map(|t| t.lexeme.as_str())
+            .collect();
+        assert_eq!(comments, vec!["// one", "//"]);
+        assert_eq!(tokens.len(), want.len() + 2);
+    }
+
+    #[test]
+    fn test_shebang() {
+        let input = "#!/usr/bin/env jilox\n1";
+        let want: Vec<Token> = vec![
+            Token::new(
+                TokenType::Number,
+                "1".to_string(),
+                Literal::Number(1.),
+                1,
+                Span::new(21, 22, 1),
+            ),
+            Token::new(
+                TokenType::EOF,
+                "".to_string(),
+                Literal::Null,
+                1,
+                Span::new(22, 22, 2),
+            ),
+        ];
+        assert_eq!(want, scan_tokens(input).unwrap());
+        assert!(scan_tokens("1 #!").is_err());
+    }
+
+    #[test]
+    fn test_rescan_matches_full_scan() {
+        let sources = [
+            "1 + 2 * (3 - 4)",
+            "ab cd\n\"x\ny\" == 12.5 // note\nfoo",
+            "变量 != café",
+        ];
+        let texts = ["", " ", "\n", "\"", "a", "=", "9.", "/", "é"];
+        for source in sources {
+            let old_tokens = scan_tokens(source).unwrap();
+            let boundaries: Vec<usize> = (0..=source.len())
+                .filter(|&i| source.is_char_boundary(i))
+                .collect();
+            for (i, &start) in boundaries.iter().enumerate() {
+                for &end in &boundaries[i..] {
+                    for text in texts {
+                        let edit = Edit::new(start..end, text.to_string());
+                        let new_source = edit.apply(source);
+                        let want = scan_tokens(&new_source).ok();
+                        let got = rescan(&old_tokens, &edit, &new_source).ok();
+                        assert_eq!(want, got, "{source:?} edited to {new_source:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_scan_from_stops_on_sync() {
+        let source = "1 + 20 + 3 + 4";
+        let (tokens, errors) = scan_from(source, 3, 0, false, |t| t.span.start >= 7);
+        assert!(errors.is_empty());
+        let lexemes: Vec<&str> = tokens.iter().map(|t| t.lexeme.as_str()).collect();
+        assert_eq!(lexemes, vec!["20", "+"]);
+    }
+
+    #[test]
+    fn test_recovers_from_errors() {
+        // Nothing closes the string, so it is cut at its line and scanning
+        // goes on, reporting the '$' after it too.
+        let source = "1. $\n\"abc\r\n2 $";
+        let (tokens, errors) = scan_tokens_recovering(source);
+        let got: Vec<(TokenType, &str, u32)> = tokens
+            .iter()
+            .map(|t| (t.token_type, &source[t.span.start..t.span.end], t.line))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                (TokenType::Number, "1.", 0),
+                (TokenType::String, "\"abc\r", 1),
+                (TokenType::Number, "2", 2),
+                (TokenType::EOF, "", 2),
+            ]
+        );
+        assert_eq!(tokens[1].literal, Literal::Text("abc".to_string()));
+
+        let codes: Vec<(ErrorCode, u32, u32)> = errors
+            .into_iter()
+            .map(|e| {
+                let (_, e) = e.into_parts().unwrap();
+                (e.code, e.line, e.span.column)
+            })
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                (ErrorCode::InvalidNumber, 0, 1),
+                (ErrorCode::UnexpectedCharacter, 0, 4),
+                (ErrorCode::UnterminatedString, 1, 1),
+                (ErrorCode::UnexpectedCharacter, 2, 3),
+            ]
+        );
     }
 }
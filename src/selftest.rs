@@ -0,0 +1,109 @@
+use jilox::{diagnostics::Diagnostics, interpreter::Interpreter};
+
+use crate::Config;
+
+/// What a conformance program is expected to produce.
+enum Expect {
+    Value(&'static str),
+    Error,
+}
+
+/// Conformance programs compiled into the binary, so a build can be checked
+/// without a checkout of the repo.
+const CASES: &[(&str, &str, Expect)] = &[
+    ("number", "123.5", Expect::Value("123.5")),
+    ("string", "\"hello\"", Expect::Value("hello")),
+    ("nil", "nil", Expect::Value("nil")),
+    ("arithmetic", "1 + 2 * 3 - 4 / 2", Expect::Value("5")),
+    ("modulo", "10 % 4", Expect::Value("2")),
+    ("power", "-2 ** 3 ** 2", Expect::Value("-512")),
+    ("bitwise", "(12 & 10) | 1 << 4", Expect::Value("24")),
+    ("list index", "[1, [2, 3]][1][0] + 1", Expect::Value("3")),
+    (
+        "map lookup",
+        "{\"a\": 1, \"b\": 2}[\"b\"]",
+        Expect::Value("2"),
+    ),
+    (
+        "string slice",
+        "\"héllo\"[1:3] + \"héllo\"[4]",
+        Expect::Value("élo"),
+    ),
+    ("range", "(0..10)[4]", Expect::Value("4")),
+    (
+        "match",
+        "match ([1, 2]) { [a, b] -> a + b; else -> 0 }",
+        Expect::Value("3"),
+    ),
+    ("grouping", "(1 + 2) * 3", Expect::Value("9")),
+    ("unary minus", "-(1 + 2)", Expect::Value("-3")),
+    ("unary bang", "!true", Expect::Value("false")),
+    (
+        "concatenation",
+        "\"foo\" + \"bar\"",
+        Expect::Value("foobar"),
+    ),
+    ("truthiness", "!nil == !false", Expect::Value("true")),
+    ("equality", "nil == false", Expect::Value("false")),
+    (
+        "comparison",
+        "\"apple\" < \"banana\"",
+        Expect::Value("true"),
+    ),
+    ("comments", "1 // one\n+ 2", Expect::Value("3")),
+    ("shebang", "#!/usr/bin/env jilox\n42", Expect::Value("42")),
+    ("string + number", "\"scone\" + 4", Expect::Value("scone4")),
+    ("mixed operands", "true + \"a\"", Expect::Error),
+    ("unterminated string", "\"abc", Expect::Error),
+    ("unclosed group", "(1 + 2", Expect::Error),
+];
+
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// Runs every case in a fresh interpreter, printing one line per case.
+pub fn run_selftest() -> Summary {
+    let mut summary = Summary {
+        passed: 0,
+        failed: 0,
+    };
+    for (name, source, expect) in CASES {
+        let mut interpreter = Interpreter::new();
+        let mut diagnostics = Diagnostics::default();
+        let outcome = crate::run(
+            &mut interpreter,
+            &Config::default(),
+            source,
+            &mut diagnostics,
+        );
+        let ok = match (expect, &outcome) {
+            (Expect::Value(want), Some(value)) => value.to_string() == *want,
+            (Expect::Error, None) => diagnostics.has_errors(),
+            _ => false,
+        };
+        if ok {
+            summary.passed += 1;
+            println!("ok      {name}");
+        } else {
+            summary.failed += 1;
+            match outcome {
+                Some(value) => println!("FAILED  {name}: got {value}"),
+                None => println!("FAILED  {name}:\n{}", diagnostics.render(source)),
+            }
+        }
+    }
+    println!("{} passed, {} failed", summary.passed, summary.failed);
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selftest_passes() {
+        assert_eq!(run_selftest().failed, 0);
+    }
+}